@@ -8,21 +8,22 @@ use oauth2::{
 };
 use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
-use tauri::State;
+use tauri::{Emitter, State};
 use tauri_plugin_webauth::{WebAuthExt, WebAuthRequest};
 
-use crate::api::get_api_client;
+use crate::api::get_froide_client;
+use crate::cache::ApiCache;
 use crate::error::{AppError, AuthorizationError, UserError};
+use crate::instance::{FroideInstance, get_instance};
 use crate::{AppState, AuthState, User};
 
 const REDIRECT_URI: &str = "fragdenstaat://loggedin";
-const CLIENT_ID: &str = "1nmNtPIiQ7xA1yqzZDwEmOlguNEhdnp5vQpGyfSd";
-const HOSTNAME: &str = "fragdenstaat.de";
-const AUTHORIZE_ENDPOINT: &str = "https://fragdenstaat.de/account/authorize/";
-const ACCESS_TOKEN_ENDPOINT: &str = "https://fragdenstaat.de/account/token/";
-const REVOKE_TOKEN_ENDPOINT: &str = "https://fragdenstaat.de/account/revoke_token/";
+const AUTHORIZE_PATH: &str = "/account/authorize/";
+const ACCESS_TOKEN_PATH: &str = "/account/token/";
+const REVOKE_TOKEN_PATH: &str = "/account/revoke_token/";
 
-const USER_ENDPOINT: &str = "https://fragdenstaat.de/api/v1/user/";
+// Emitted when the access token can not be refreshed and the user has to log in again
+const SESSION_EXPIRED_EVENT: &str = "session-expired";
 
 // Minimum duration in seconds before token expiration to refresh it
 const MIN_DUATION_BEFORE_REFRESH: u64 = 60 * 60;
@@ -59,46 +60,53 @@ pub async fn get_user(
 ) -> Result<User, AppError> {
     log::info!("get user in main called");
 
-    ensure_valid_token(&app_handle, &state).await?;
-
-    let mut tries = 0;
-    let mut response;
-
-    loop {
-        tries += 1;
-        let client = get_api_client(&state)?;
-        let request = client.get(USER_ENDPOINT);
-
-        response = request.send().await?;
-        if response.status().is_client_error() && tries < 2 {
-            refresh_token(&app_handle, &state).await?;
-            continue;
+    let user = match fetch_user(&app_handle, &state).await {
+        Ok(user) => user,
+        Err(err) if err.is_connection_error() => {
+            // Continue offline with the user of the last session
+            let state = state.lock().unwrap();
+            match state.user {
+                Some(ref user) => {
+                    log::warn!("Using stored user while offline: {err}");
+                    return Ok(user.clone());
+                }
+                None => return Err(err),
+            }
         }
-        break;
-    }
-
-    let user = response.json::<User>().await?;
+        Err(err) => return Err(err),
+    };
 
     {
         let mut state = state.lock().unwrap();
         state.user = Some(user.clone());
+        state.save(&app_handle)?;
     }
 
     Ok(user)
 }
 
+async fn fetch_user(
+    app_handle: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+) -> Result<User, AppError> {
+    ensure_valid_token(app_handle, state).await?;
+
+    let client = get_froide_client(app_handle, state);
+    client.get_user().await
+}
+
 pub async fn ensure_valid_token(
     app_handle: &tauri::AppHandle,
     state: &State<'_, Mutex<AppState>>,
 ) -> Result<(), AppError> {
-    let token_valid_duration = {
+    let (access_token, token_valid_duration) = {
         let state = state.lock().unwrap();
-        let expires_at = match state.auth {
-            Some(ref auth_state) => auth_state.expires_at,
+        let (access_token, expires_at) = match state.auth {
+            Some(ref auth_state) => (auth_state.access_token.clone(), auth_state.expires_at),
             None => return Err(UserError("No auth state found".to_string()).into()),
         };
 
-        expires_at.map_or(Duration::ZERO, |expires_at| {
+        let token_valid_duration = expires_at.map_or(Duration::ZERO, |expires_at| {
             Duration::from_secs(expires_at)
                 .checked_sub(
                     SystemTime::now()
@@ -106,59 +114,156 @@ pub async fn ensure_valid_token(
                         .unwrap(),
                 )
                 .unwrap_or(Duration::ZERO)
-        })
+        });
+        (access_token, token_valid_duration)
     };
 
     // Refresh token if it expires soon
     if token_valid_duration < Duration::from_secs(MIN_DUATION_BEFORE_REFRESH) {
-        refresh_token(app_handle, state).await?;
+        refresh_token(app_handle, state, &access_token).await?;
     }
 
     Ok(())
 }
 
+/// Outcome of the last token refresh, shared with callers that waited for it.
+#[derive(Default)]
+pub struct TokenRefresh {
+    /// Access token that could not be refreshed
+    failed_token: Option<String>,
+}
+
+/// Refresh the access token `stale_token` that was rejected or is about to expire.
+/// Concurrent callers wait for a single refresh and share its outcome,
+/// because a rotated refresh token can only be used once.
 pub async fn refresh_token(
     app_handle: &tauri::AppHandle,
     state: &State<'_, Mutex<AppState>>,
+    stale_token: &str,
 ) -> Result<(), AppError> {
-    let oauth2_client = get_outh2_client()?;
+    let token_refresh = state.lock().unwrap().token_refresh.clone();
+    let mut last_refresh = token_refresh.lock().await;
+    let current_token = {
+        let state = state.lock().unwrap();
+        state
+            .auth
+            .as_ref()
+            .map(|auth_state| auth_state.access_token.clone())
+    };
+    match current_token {
+        None => return Err(AuthorizationError("Not logged in".to_string()).into()),
+        // Another caller already replaced the token
+        Some(current_token) if current_token != stale_token => return Ok(()),
+        Some(_) => {}
+    }
+    if last_refresh.failed_token.as_deref() == Some(stale_token) {
+        return Err(AuthorizationError("Could not refresh access token".to_string()).into());
+    }
+
+    let result = request_token_refresh(app_handle, state).await;
+    *last_refresh = match result {
+        // The refresh token was not used, so the next caller can try again
+        Err(ref err) if err.is_connection_error() => TokenRefresh::default(),
+        Err(_) => TokenRefresh {
+            failed_token: Some(stale_token.to_string()),
+        },
+        Ok(()) => TokenRefresh::default(),
+    };
+    result
+}
+
+async fn request_token_refresh(
+    app_handle: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+) -> Result<(), AppError> {
+    let oauth2_client = get_outh2_client(&get_instance(state))?;
     let refresh_token = {
         let state = state.lock().unwrap();
-        match state.auth {
-            Some(ref auth_state) => auth_state
-                .refresh_token
-                .clone()
-                .ok_or(AuthorizationError("Missing refresh token".to_string())),
-            None => Err(AuthorizationError("Missing auth state".to_string())),
-        }
-    }?;
+        state
+            .auth
+            .as_ref()
+            .and_then(|auth_state| auth_state.refresh_token.clone())
+    };
+    let refresh_token = match refresh_token {
+        Some(refresh_token) => refresh_token,
+        None => return Err(expire_session(app_handle, state, "Missing refresh token")),
+    };
 
     let token_result = oauth2_client
         .exchange_refresh_token(&RefreshToken::new(refresh_token))
         .request_async(async_http_client)
         .await;
     let token_result = match token_result {
-        Ok(token) => Ok(token),
-        Err(_err) => Err(AuthorizationError(
-            "Could not refresh access token".to_string(),
-        )),
-    }?;
+        Ok(token) => token,
+        Err(oauth2::RequestTokenError::Request(err)) => {
+            // The server could not be reached, the refresh token may still be valid
+            log::error!("Failed to refresh token: {err:?}");
+            return Err(AppError::ConnectionError(
+                "Could not refresh access token".to_string(),
+            ));
+        }
+        Err(err) => {
+            log::error!("Failed to refresh token: {err:?}");
+            return Err(expire_session(
+                app_handle,
+                state,
+                "Refresh token was rejected",
+            ));
+        }
+    };
 
     store_token_result(app_handle, state, token_result)?;
 
     Ok(())
 }
 
+/// Forget the tokens that can not be used anymore and tell the frontend to log in again.
+/// Pending uploads are kept, so they can continue after logging in.
+fn expire_session(
+    app_handle: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    reason: &str,
+) -> AppError {
+    log::warn!("Session expired: {reason}");
+    {
+        let mut state = state.lock().unwrap();
+        state.auth = None;
+        state.user = None;
+        if let Err(err) = state.save(app_handle) {
+            log::error!("Failed to save expired session: {err}");
+        }
+    }
+    if let Err(err) = app_handle.emit(SESSION_EXPIRED_EVENT, ()) {
+        log::error!("Failed to emit session expired event: {err}");
+    }
+    AuthorizationError("Session expired, please log in again".to_string()).into()
+}
+
 #[tauri::command(rename_all = "snake_case")]
 pub async fn start_oauth(
     app_handle: tauri::AppHandle,
     state: State<'_, Mutex<AppState>>,
     start_url: Option<String>,
+    instance: Option<FroideInstance>,
 ) -> Result<bool, AppError> {
+    let instance = match instance {
+        Some(instance) => {
+            let instance = instance.validate()?;
+            let state = state.lock().unwrap();
+            if state.auth.is_some() && state.instance != instance {
+                return Err(
+                    UserError("Log out before switching to another instance".to_string()).into(),
+                );
+            }
+            instance
+        }
+        None => get_instance(&state),
+    };
+
     let verified_start_url = match start_url {
         Some(url) => {
             let url = Url::parse(url.as_str())?;
-            if url.host_str() != Some(HOSTNAME) {
+            if !instance.is_allowed_host(url.host_str()) {
                 return Err(AuthorizationError("Invalid start URL".to_string()).into());
             }
             Some(url)
@@ -166,7 +271,7 @@ pub async fn start_oauth(
         None => None,
     };
 
-    let oauth2_client = get_outh2_client()?;
+    let oauth2_client = get_outh2_client(&instance)?;
     let auth_data = get_oauth_url(&oauth2_client)?;
 
     let auth_url = match verified_start_url {
@@ -263,6 +368,10 @@ pub async fn start_oauth(
         },
     };
 
+    {
+        let mut state = state.lock().unwrap();
+        state.instance = instance;
+    }
     store_token_result(&app_handle, &state, token_result)?;
 
     Ok(true)
@@ -295,18 +404,18 @@ fn store_token_result(
     Ok(())
 }
 
-fn get_outh2_client() -> Result<BasicClient, AppError> {
+fn get_outh2_client(instance: &FroideInstance) -> Result<BasicClient, AppError> {
     // Create an OAuth2 client by specifying the client ID, client secret, authorization URL and
     // token URL.
     let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
+        ClientId::new(instance.client_id.clone()),
         None,
-        AuthUrl::new(AUTHORIZE_ENDPOINT.to_string())?,
-        Some(TokenUrl::new(ACCESS_TOKEN_ENDPOINT.to_string())?),
+        AuthUrl::new(instance.url(AUTHORIZE_PATH))?,
+        Some(TokenUrl::new(instance.url(ACCESS_TOKEN_PATH))?),
     )
     // Set the URL the user will be redirected to after the authorization process.
     .set_redirect_uri(RedirectUrl::new(REDIRECT_URI.to_string())?)
-    .set_revocation_uri(RevocationUrl::new(REVOKE_TOKEN_ENDPOINT.to_string())?);
+    .set_revocation_uri(RevocationUrl::new(instance.url(REVOKE_TOKEN_PATH))?);
     Ok(client)
 }
 
@@ -334,14 +443,25 @@ fn get_oauth_url(client: &BasicClient) -> Result<OAuthData, AppError> {
     })
 }
 
-#[tauri::command]
+/// Log out and revoke the tokens. Scans that were not uploaded yet are deleted,
+/// so the frontend has to confirm this with `discard_uploads`.
+#[tauri::command(rename_all = "snake_case")]
 pub async fn logout(
     app_handle: tauri::AppHandle,
     state: State<'_, Mutex<AppState>>,
+    discard_uploads: Option<bool>,
 ) -> Result<bool, AppError> {
     log::info!("start logout in main called");
 
-    let oauth2_client = get_outh2_client()?;
+    let queued_jobs = state.lock().unwrap().upload_queue.len();
+    if queued_jobs > 0 && !discard_uploads.unwrap_or(false) {
+        return Err(UserError(format!(
+            "{queued_jobs} scanned documents have not been uploaded yet"
+        ))
+        .into());
+    }
+
+    let oauth2_client = get_outh2_client(&get_instance(&state))?;
 
     let (access_token, refresh_token) = {
         let state = state.lock().unwrap();
@@ -378,13 +498,19 @@ pub async fn logout(
         }
     }
 
+    if let Some(cache) = ApiCache::open(&app_handle, &state)? {
+        cache.clear()?;
+    }
+
     {
         let mut state = state.lock().unwrap();
         state.auth = None;
         state.user = None;
-        state.upload_url = None;
-        state.file_path = None;
-        state.message_resource_uri = None;
+        for job in state.upload_queue.drain(..) {
+            if let Err(err) = job.remove_file() {
+                log::warn!("Failed to remove scan of upload job {}: {err}", job.id);
+            }
+        }
         state.save(&app_handle)?;
     }
     Ok(true)
@@ -1,57 +1,234 @@
-use reqwest::header;
-use serde::de::DeserializeOwned;
-
-use std::collections::HashMap;
-use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use futures_util::future::BoxFuture;
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Emitter, State};
+use tauri::{AppHandle, Manager, State};
 
-use crate::error::{AppError, TusError, UserError};
-use crate::tus::TusClient;
+use crate::account::refresh_token;
+use crate::cache::{ApiCache, cached};
+use crate::client::{FroideClient, TokenProvider};
+use crate::error::{AppError, AuthorizationError, UserError};
+use crate::queue::remove_message_jobs;
+use crate::scan::discard_job_uploads;
+use crate::sync::{
+    remove_cached_message, sync_requests, update_cached_message, update_cached_request,
+};
 use crate::{AppState, UserId};
-use chrono::prelude::*;
-
-const REQUEST_ENDPOINT: &str = "https://fragdenstaat.de/api/v1/request/";
-const MESSAGE_ENDPOINT: &str = "https://fragdenstaat.de/api/v1/message/";
-const UPLOAD_ENDPOINT: &str = "https://fragdenstaat.de/api/v1/upload/";
-const ATTACHMENT_ENDPOINT: &str = "https://fragdenstaat.de/api/v1/attachment/";
-const UPLOAD_URL_BASE: &str = "https://fragdenstaat.de";
 
-type FoiRequestId = u64;
+pub type FoiRequestId = u64;
 pub type MessageId = u64;
-type FoiMessageId = u64;
+pub type FoiMessageId = u64;
 pub type FoiAttachmentId = u64;
 
+pub type PublicBodyId = u64;
+pub type JurisdictionId = u64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Jurisdiction {
+    pub id: JurisdictionId,
+    name: String,
+    #[serde(default)]
+    resource_uri: String,
+    #[serde(default)]
+    slug: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PublicBody {
-    id: u64,
+    pub id: PublicBodyId,
     name: String,
     resource_uri: String,
+    #[serde(default)]
+    slug: String,
+    /// Abbreviations and former names, comma separated
+    #[serde(default)]
+    other_names: String,
+    #[serde(default)]
+    site_url: String,
+    #[serde(default)]
+    jurisdiction: Option<Jurisdiction>,
+    #[serde(default)]
+    number_of_requests: u64,
+}
+
+/// Where a request stands in Froide's workflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FoiRequestStatus {
+    AwaitingUserConfirmation,
+    PublicbodyNeeded,
+    AwaitingPublicbodyConfirmation,
+    #[default]
+    AwaitingResponse,
+    AwaitingClassification,
+    Asleep,
+    Resolved,
+    /// Status of a newer Froide version
+    #[serde(other)]
+    Unknown,
+}
+
+impl FoiRequestStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FoiRequestStatus::AwaitingUserConfirmation => "awaiting_user_confirmation",
+            FoiRequestStatus::PublicbodyNeeded => "publicbody_needed",
+            FoiRequestStatus::AwaitingPublicbodyConfirmation => "awaiting_publicbody_confirmation",
+            FoiRequestStatus::AwaitingResponse => "awaiting_response",
+            FoiRequestStatus::AwaitingClassification => "awaiting_classification",
+            FoiRequestStatus::Asleep => "asleep",
+            FoiRequestStatus::Resolved => "resolved",
+            FoiRequestStatus::Unknown => "unknown",
+        }
+    }
+}
+
+/// The outcome of a request, `Unresolved` until it is resolved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FoiRequestResolution {
+    #[default]
+    #[serde(rename = "")]
+    Unresolved,
+    Successful,
+    PartiallySuccessful,
+    NotHeld,
+    Refused,
+    UserWithdrewCosts,
+    UserWithdrew,
+    /// Resolution of a newer Froide version
+    #[serde(other)]
+    Unknown,
+}
+
+impl FoiRequestResolution {
+    /// Whether the resolution can be chosen when resolving a request.
+    fn is_final(self) -> bool {
+        !matches!(
+            self,
+            FoiRequestResolution::Unresolved | FoiRequestResolution::Unknown
+        )
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FoiRequest {
-    id: FoiRequestId,
+    pub id: FoiRequestId,
     resource_uri: String,
     url: String,
     title: String,
     created_at: String,
-    last_message: String,
+    pub last_message: String,
     public_body: PublicBody,
+    #[serde(default)]
+    status: FoiRequestStatus,
+    #[serde(default)]
+    resolution: FoiRequestResolution,
+    /// When the public body has to answer (RFC 3339)
+    #[serde(default)]
+    due_date: Option<String>,
+    #[serde(default)]
+    resolved_on: Option<String>,
+    /// Costs charged by the public body in Euro
+    #[serde(default)]
+    costs: f64,
+    /// Resource URI of the law the request is based on
+    #[serde(default)]
+    law: Option<String>,
+    /// Resource URI of the jurisdiction of the law
+    #[serde(default)]
+    jurisdiction: Option<String>,
+    /// Visible to everyone, otherwise only to the requester
+    #[serde(default)]
+    public: bool,
+    /// Reference number, e.g. the file number of the public body
+    #[serde(default)]
+    reference: String,
+}
+
+/// How a message was exchanged with the public body.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Email,
+    #[default]
+    Post,
+    Fax,
+    Upload,
+    Phone,
+    /// In-person visit, e.g. to inspect files
+    Visit,
+    Import,
+    /// Kind of a newer Froide version
+    #[serde(other)]
+    Unknown,
+}
+
+impl MessageKind {
+    /// Messages of these kinds are logged by the user and shown in the app.
+    /// Email is sent through the website and imports are done by staff.
+    pub const APP_KINDS: [MessageKind; 5] = [
+        MessageKind::Post,
+        MessageKind::Fax,
+        MessageKind::Upload,
+        MessageKind::Phone,
+        MessageKind::Visit,
+    ];
+
+    fn can_create(self) -> bool {
+        Self::APP_KINDS.contains(&self)
+    }
+
+    /// Value of the `kind` filter of the message API, `None` for unknown kinds.
+    pub fn api_name(self) -> Option<&'static str> {
+        match self {
+            MessageKind::Email => Some("email"),
+            MessageKind::Post => Some("post"),
+            MessageKind::Fax => Some("fax"),
+            MessageKind::Upload => Some("upload"),
+            MessageKind::Phone => Some("phone"),
+            MessageKind::Visit => Some("visit"),
+            MessageKind::Import => Some("import"),
+            MessageKind::Unknown => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FoiMessage {
-    id: MessageId,
+    pub id: MessageId,
     resource_uri: String,
     request: String,
-    timestamp: String,
+    pub timestamp: String,
     is_response: bool,
     is_draft: bool,
     sender: Option<String>,
     subject: String,
+    #[serde(default)]
+    pub kind: MessageKind,
+    #[serde(default)]
+    sender_public_body: Option<String>,
+    #[serde(default)]
+    recipient_public_body: Option<String>,
+    /// Plain text of the letter or a summary of it
+    #[serde(default)]
+    content: String,
+    /// Set if the letter was sent as registered mail
+    #[serde(default)]
+    registered_mail_date: Option<String>,
+}
+
+impl FoiMessage {
+    /// Id of the request, taken from the request URI like `/api/v1/request/123/`.
+    pub fn request_id(&self) -> Option<FoiRequestId> {
+        self.request
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()?
+            .parse()
+            .ok()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -68,7 +245,7 @@ pub struct FoiAttachment {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Meta {
     limit: u32,
-    next: Option<String>,
+    pub next: Option<String>,
     offset: u32,
     previous: Option<String>,
     total_count: u32,
@@ -76,33 +253,39 @@ pub struct Meta {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
-    meta: Meta,
-    objects: Vec<T>,
+    pub meta: Meta,
+    pub objects: Vec<T>,
 }
 
-pub fn get_api_client(
-    state: &State<'_, Mutex<AppState>>,
-) -> Result<reqwest::Client, reqwest::Error> {
-    let access_token = {
+/// Reads the access token of the logged in user from the app state
+/// and refreshes it with the stored refresh token.
+struct AppStateTokens(AppHandle);
+
+impl TokenProvider for AppStateTokens {
+    fn access_token(&self) -> Result<String, AppError> {
+        let state = self.0.state::<Mutex<AppState>>();
         let state = state.lock().unwrap();
-        let auth_state = state.auth.as_ref().unwrap();
-        auth_state.access_token.clone()
-    };
-    let mut headers = header::HeaderMap::new();
+        match state.auth {
+            Some(ref auth_state) => Ok(auth_state.access_token.clone()),
+            None => Err(AuthorizationError("Not logged in".to_string()).into()),
+        }
+    }
 
-    headers.insert(
-        "Accept",
-        header::HeaderValue::from_static("application/json"),
-    );
-    let mut auth_value =
-        header::HeaderValue::from_str(format!("Bearer {access_token}").as_str()).unwrap();
-    auth_value.set_sensitive(true);
-    headers.insert(header::AUTHORIZATION, auth_value);
+    fn refresh<'a>(&'a self, rejected_token: &'a str) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move { refresh_token(&self.0, &self.0.state(), rejected_token).await })
+    }
+}
 
-    reqwest::Client::builder().default_headers(headers).build()
+/// API client for the current instance that always uses the latest access token.
+pub fn get_froide_client(app: &AppHandle, state: &State<'_, Mutex<AppState>>) -> FroideClient {
+    let (instance, http_client) = {
+        let state = state.lock().unwrap();
+        (state.instance.clone(), state.http_client.clone())
+    };
+    FroideClient::new(instance, http_client, Arc::new(AppStateTokens(app.clone())))
 }
 
-fn get_user_id(state: &State<'_, Mutex<AppState>>) -> Result<UserId, AppError> {
+pub fn get_user_id(state: &State<'_, Mutex<AppState>>) -> Result<UserId, AppError> {
     let state = state.lock().unwrap();
 
     let user_id = match state.user {
@@ -112,211 +295,766 @@ fn get_user_id(state: &State<'_, Mutex<AppState>>) -> Result<UserId, AppError> {
     Ok(user_id)
 }
 
-#[tauri::command]
+/// Sync the requests of the user, see [`sync_requests`].
+/// `full_sync` fetches all requests instead of only the changed ones.
+#[tauri::command(rename_all = "snake_case")]
 pub async fn get_foirequests(
     app: AppHandle,
     state: State<'_, Mutex<AppState>>,
+    full_sync: Option<bool>,
 ) -> Result<bool, AppError> {
-    let user_id = get_user_id(&state)?;
-    let client = get_api_client(&state)?;
+    sync_requests(&app, &state, full_sync.unwrap_or(false)).await
+}
+
+// Requests per page of search results if the frontend does not ask for a size
+const SEARCH_PAGE_SIZE: u32 = 20;
 
-    let mut next = Some(format!("{REQUEST_ENDPOINT}?user={user_id}"));
-    while let Some(next_url) = next {
-        let response = client.get(next_url).send().await?;
-        let api_response = response.json::<ApiResponse<FoiRequest>>().await?;
-        // Emit early to show progress
-        app.emit("foirequest-list", &api_response.objects)?;
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestOrdering {
+    Newest,
+    Oldest,
+    LastMessage,
+    FirstMessage,
+}
 
-        next = api_response.meta.next;
+impl RequestOrdering {
+    fn as_query(self) -> &'static str {
+        match self {
+            RequestOrdering::Newest => "-created_at",
+            RequestOrdering::Oldest => "created_at",
+            RequestOrdering::LastMessage => "-last_message",
+            RequestOrdering::FirstMessage => "last_message",
+        }
     }
-    Ok(true)
 }
 
+/// Filters for searching the requests of the user, all are optional.
+#[derive(Debug, Default, Deserialize)]
+pub struct RequestFilter {
+    /// Full text search
+    query: Option<String>,
+    status: Option<FoiRequestStatus>,
+    public_body: Option<u64>,
+    jurisdiction: Option<u64>,
+    /// Created on or after this day, `YYYY-MM-DD`
+    created_after: Option<String>,
+    /// Created before this day, `YYYY-MM-DD`
+    created_before: Option<String>,
+    ordering: Option<RequestOrdering>,
+}
+
+impl RequestFilter {
+    pub fn query(&self) -> Option<&str> {
+        self.query
+            .as_deref()
+            .filter(|query| !query.trim().is_empty())
+    }
+
+    /// The filters as query parameters of the Froide API.
+    pub fn query_pairs(&self) -> Result<Vec<(&'static str, String)>, AppError> {
+        let mut pairs = vec![];
+        if let Some(query) = self.query() {
+            pairs.push(("q", query.trim().to_string()));
+        }
+        if let Some(status) = self.status {
+            pairs.push(("status", status.as_str().to_string()));
+        }
+        if let Some(public_body) = self.public_body {
+            pairs.push(("public_body", public_body.to_string()));
+        }
+        if let Some(jurisdiction) = self.jurisdiction {
+            pairs.push(("jurisdiction", jurisdiction.to_string()));
+        }
+        for (name, date) in [
+            ("created_at_after", &self.created_after),
+            ("created_at_before", &self.created_before),
+        ] {
+            if let Some(date) = date {
+                if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+                    return Err(UserError(format!("Invalid date: {date}")).into());
+                }
+                pairs.push((name, date.clone()));
+            }
+        }
+        if let Some(ordering) = self.ordering {
+            pairs.push(("ordering", ordering.as_query().to_string()));
+        }
+        Ok(pairs)
+    }
+}
+
+/// One page of the requests of the user matching `filter`.
 #[tauri::command(rename_all = "snake_case")]
-pub async fn get_foirequest(
+pub async fn search_foirequests(
+    app: AppHandle,
     state: State<'_, Mutex<AppState>>,
-    request_id: FoiRequestId,
-) -> Result<FoiRequest, AppError> {
-    let client = get_api_client(&state)?;
+    filter: RequestFilter,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<ApiResponse<FoiRequest>, AppError> {
+    let user_id = get_user_id(&state)?;
+    let client = get_froide_client(&app, &state);
+    client
+        .search_requests(
+            user_id,
+            &filter,
+            offset.unwrap_or(0),
+            limit.unwrap_or(SEARCH_PAGE_SIZE),
+        )
+        .await
+}
 
-    let url = format!("{REQUEST_ENDPOINT}{request_id}/");
-    let response = client.get(url).send().await?;
-    let api_response = response.json::<FoiRequest>().await?;
+/// Filters for searching public bodies, e.g. the sender of a forwarded reply.
+#[derive(Debug, Default, Deserialize)]
+pub struct PublicBodyFilter {
+    /// Searches names and abbreviations
+    query: Option<String>,
+    jurisdiction: Option<JurisdictionId>,
+}
 
-    Ok(api_response)
+impl PublicBodyFilter {
+    /// The filters as query parameters of the Froide API.
+    pub fn query_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![];
+        if let Some(query) = self.query.as_deref().map(str::trim)
+            && !query.is_empty()
+        {
+            pairs.push(("q", query.to_string()));
+        }
+        if let Some(jurisdiction) = self.jurisdiction {
+            pairs.push(("jurisdiction", jurisdiction.to_string()));
+        }
+        pairs
+    }
 }
 
-pub async fn get_all_objects<T>(
-    url: String,
-    state: &State<'_, Mutex<AppState>>,
-) -> Result<Vec<T>, AppError>
-where
-    T: Clone + Serialize + DeserializeOwned,
-{
-    let client = get_api_client(state)?;
+/// One page of the public bodies matching `filter`.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn search_publicbodies(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    filter: PublicBodyFilter,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<ApiResponse<PublicBody>, AppError> {
+    let client = get_froide_client(&app, &state);
+    client
+        .search_public_bodies(
+            &filter,
+            offset.unwrap_or(0),
+            limit.unwrap_or(SEARCH_PAGE_SIZE),
+        )
+        .await
+}
 
-    let mut objects: Vec<T> = vec![];
-    let mut next = Some(url);
+/// Status change of a request as the requester can do it on the website.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateRequestStatus {
+    status: FoiRequestStatus,
+    resolution: FoiRequestResolution,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    costs: Option<f64>,
+}
 
-    while let Some(ref next_url) = next {
-        let response = client.get(next_url).send().await?;
-        // let text = response.text().await?;
-        // log::info!("Response: {}", text);
-        // let api_response: ApiResponse<T> = serde_json::from_str(&text).unwrap();
-        let api_response = response.json::<ApiResponse<T>>().await?;
+impl UpdateRequestStatus {
+    /// Check that the requester may change `request` to this status.
+    fn validate(&self, request: &FoiRequest) -> Result<(), AppError> {
+        if matches!(
+            request.status,
+            FoiRequestStatus::AwaitingUserConfirmation
+                | FoiRequestStatus::PublicbodyNeeded
+                | FoiRequestStatus::AwaitingPublicbodyConfirmation
+        ) {
+            return Err(UserError(format!(
+                "The status of request {} can only be changed after it was sent",
+                request.id
+            ))
+            .into());
+        }
+        match self.status {
+            FoiRequestStatus::Resolved if !self.resolution.is_final() => {
+                return Err(UserError("A resolved request needs a resolution".to_string()).into());
+            }
+            FoiRequestStatus::AwaitingResponse
+                if self.resolution != FoiRequestResolution::Unresolved =>
+            {
+                return Err(UserError(
+                    "A request awaiting a response can not have a resolution".to_string(),
+                )
+                .into());
+            }
+            FoiRequestStatus::Resolved | FoiRequestStatus::AwaitingResponse => {}
+            status => {
+                return Err(
+                    UserError(format!("The status can not be set to {}", status.as_str())).into(),
+                );
+            }
+        }
+        if let Some(costs) = self.costs
+            && !(costs.is_finite() && costs >= 0.0)
+        {
+            return Err(UserError(format!("Invalid costs: {costs}")).into());
+        }
+        Ok(())
+    }
+}
 
-        objects.extend_from_slice(&api_response.objects);
+/// Set status, resolution and costs of a request, e.g. after scanning the final reply.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn update_foirequest_status(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    request_id: FoiRequestId,
+    update: UpdateRequestStatus,
+) -> Result<FoiRequest, AppError> {
+    let client = get_froide_client(&app, &state);
+    // Validate against the current status, the cached one may be outdated
+    let request = client.get_request(request_id).await?;
+    update.validate(&request)?;
 
-        next = api_response.meta.next;
+    let request = client.update_request(request_id, &update).await?;
+    if let Some(cache) = ApiCache::open(&app, &state)? {
+        update_cached_request(&app, &cache, &request);
     }
-    Ok(objects)
+    Ok(request)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub async fn get_foirequest(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    request_id: FoiRequestId,
+) -> Result<FoiRequest, AppError> {
+    let client = get_froide_client(&app, &state);
+    let key = format!("request/{request_id}");
+    cached(
+        &app,
+        &state,
+        key,
+        "foirequest-updated",
+        move || async move { client.get_request(request_id).await },
+    )
+    .await
 }
 
+/// Messages of a request, newest first. `kinds` defaults to the kinds shown in the app.
 #[tauri::command(rename_all = "snake_case")]
 pub async fn get_foimessages(
+    app: AppHandle,
     state: State<'_, Mutex<AppState>>,
     foirequest_id: FoiRequestId,
+    kinds: Option<Vec<MessageKind>>,
 ) -> Result<Vec<FoiMessage>, AppError> {
-    let message_url = format!("{MESSAGE_ENDPOINT}?request={foirequest_id}&kind=post");
-    let mut messages = get_all_objects::<FoiMessage>(message_url, &state).await?;
-    messages.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-    messages.reverse();
-    Ok(messages)
+    let client = get_froide_client(&app, &state);
+    let kinds = kinds.unwrap_or_else(|| MessageKind::APP_KINDS.to_vec());
+    if !kinds
+        .iter()
+        .all(|kind| MessageKind::APP_KINDS.contains(kind))
+    {
+        // Only the kinds shown in the app are cached, requests can have lots of emails
+        return client.get_messages(foirequest_id, &kinds).await;
+    }
+    let key = format!("messages/{foirequest_id}");
+    let messages: Vec<FoiMessage> =
+        cached(&app, &state, key, "foimessage-list", move || async move {
+            client
+                .get_messages(foirequest_id, &MessageKind::APP_KINDS)
+                .await
+        })
+        .await?;
+    Ok(messages
+        .into_iter()
+        .filter(|message| kinds.contains(&message.kind))
+        .collect())
 }
 
 #[tauri::command(rename_all = "snake_case")]
 pub async fn get_foimessage(
+    app: AppHandle,
     state: State<'_, Mutex<AppState>>,
     foimessage_id: FoiMessageId,
 ) -> Result<FoiMessage, AppError> {
-    let client = get_api_client(&state)?;
-
-    let url = format!("{MESSAGE_ENDPOINT}{foimessage_id}/");
-    let response = client.get(url).send().await?;
-    let api_response = response.json::<FoiMessage>().await?;
-
-    Ok(api_response)
+    let client = get_froide_client(&app, &state);
+    let key = format!("message/{foimessage_id}");
+    cached(
+        &app,
+        &state,
+        key,
+        "foimessage-updated",
+        move || async move { client.get_message(foimessage_id).await },
+    )
+    .await
 }
 
+// Longest subject Froide stores
+const MAX_SUBJECT_LENGTH: usize = 255;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateMessage {
     request: String,
-    kind: String,
+    kind: MessageKind,
     timestamp: String,
     is_response: bool,
     recipient_public_body: Option<String>,
     sender_public_body: Option<String>,
+    #[serde(default)]
+    subject: String,
+    /// Plain text of the letter or a summary of it
+    #[serde(default)]
+    plaintext: String,
+    /// When the registered mail was sent or received, `None` for ordinary letters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    registered_mail_date: Option<String>,
+}
+
+/// Whether `value` is a date (`YYYY-MM-DD`) or a date with time,
+/// with or without offset as the date picker of the frontend returns it.
+fn is_valid_date(value: &str) -> bool {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok()
+        || value.parse::<NaiveDateTime>().is_ok()
+        || DateTime::parse_from_rfc3339(value).is_ok()
+}
+
+impl CreateMessage {
+    fn validate(&self) -> Result<(), AppError> {
+        if !self.kind.can_create() {
+            return Err(UserError(format!(
+                "Messages of kind {:?} can not be created",
+                self.kind
+            ))
+            .into());
+        }
+        // The public body the letter, fax or call came from or went to
+        let public_body = match self.is_response {
+            true => &self.sender_public_body,
+            false => &self.recipient_public_body,
+        };
+        let needs_public_body = !matches!(self.kind, MessageKind::Upload);
+        if needs_public_body && public_body.is_none() {
+            return Err(UserError(match self.is_response {
+                true => "The sender public body is missing".to_string(),
+                false => "The recipient public body is missing".to_string(),
+            })
+            .into());
+        }
+        // Calls and visits have no document, the notes are all there is
+        if matches!(self.kind, MessageKind::Phone | MessageKind::Visit)
+            && self.plaintext.trim().is_empty()
+        {
+            return Err(UserError(format!(
+                "A {:?} message needs a summary of what was said",
+                self.kind
+            ))
+            .into());
+        }
+        if self.registered_mail_date.is_some() && self.kind != MessageKind::Post {
+            return Err(
+                UserError("Only letters can be sent as registered mail".to_string()).into(),
+            );
+        }
+        if self.subject.chars().count() > MAX_SUBJECT_LENGTH {
+            return Err(UserError(format!(
+                "The subject can be at most {MAX_SUBJECT_LENGTH} characters long"
+            ))
+            .into());
+        }
+        if let Some(ref date) = self.registered_mail_date
+            && !is_valid_date(date)
+        {
+            return Err(UserError(format!("Invalid registered mail date: {date}")).into());
+        }
+        Ok(())
+    }
 }
 
 #[tauri::command(rename_all = "snake_case")]
 pub async fn create_foimessage(
+    app: AppHandle,
     state: State<'_, Mutex<AppState>>,
     message: CreateMessage,
 ) -> Result<FoiMessage, AppError> {
-    let client = get_api_client(&state)?;
-    let response = client.post(MESSAGE_ENDPOINT).json(&message).send().await?;
-    let api_response = response.json::<FoiMessage>().await?;
-    Ok(api_response)
+    message.validate()?;
+    let client = get_froide_client(&app, &state);
+    client.create_message(&message).await
+}
+
+/// Changes to a draft message, fields that are `None` are left as they are.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateDraftMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender_public_body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recipient_public_body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subject: Option<String>,
+}
+
+impl UpdateDraftMessage {
+    fn validate(&self) -> Result<(), AppError> {
+        if let Some(ref timestamp) = self.timestamp
+            && !is_valid_date(timestamp)
+        {
+            return Err(UserError(format!("Invalid date: {timestamp}")).into());
+        }
+        if let Some(ref subject) = self.subject
+            && subject.chars().count() > MAX_SUBJECT_LENGTH
+        {
+            return Err(UserError(format!(
+                "The subject can be at most {MAX_SUBJECT_LENGTH} characters long"
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Fetch the current version of a message and make sure it is still a draft.
+async fn get_draft(
+    client: &FroideClient,
+    message_id: FoiMessageId,
+) -> Result<FoiMessage, AppError> {
+    let message = client.get_message(message_id).await?;
+    if !message.is_draft {
+        return Err(UserError("Only draft messages can be changed".to_string()).into());
+    }
+    Ok(message)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub async fn update_foimessage(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    foimessage_id: FoiMessageId,
+    update: UpdateDraftMessage,
+) -> Result<FoiMessage, AppError> {
+    update.validate()?;
+    let client = get_froide_client(&app, &state);
+    get_draft(&client, foimessage_id).await?;
+
+    let message = client.update_message(foimessage_id, &update).await?;
+    if let Some(cache) = ApiCache::open(&app, &state)? {
+        update_cached_message(&app, &cache, &message);
+    }
+    Ok(message)
+}
+
+/// Delete a draft message, queued uploads for it are discarded with their scanned files
+/// and removed from the server.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn delete_foimessage(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    foimessage_id: FoiMessageId,
+) -> Result<bool, AppError> {
+    let client = get_froide_client(&app, &state);
+    let message = get_draft(&client, foimessage_id).await?;
+
+    client.delete_message(foimessage_id).await?;
+    if let Some(cache) = ApiCache::open(&app, &state)? {
+        remove_cached_message(&app, &cache, &message);
+    }
+    for job in remove_message_jobs(&app, &state, &message.resource_uri)? {
+        job.remove_file()?;
+        // The message is already gone, leftover uploads expire on the server
+        if let Err(err) = discard_job_uploads(&app, &state, &job).await {
+            log::warn!("Could not remove uploads of job {}: {err}", job.id);
+        }
+    }
+    Ok(true)
+}
+
+/// Publish a draft message once its scans are uploaded.
+/// Letters, faxes and uploads need at least one attachment.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn publish_foimessage(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    foimessage_id: FoiMessageId,
+) -> Result<FoiMessage, AppError> {
+    let client = get_froide_client(&app, &state);
+    let message = get_draft(&client, foimessage_id).await?;
+    let pending_uploads = {
+        let state = state.lock().unwrap();
+        state
+            .upload_queue
+            .iter()
+            .any(|job| job.message_resource_uri == message.resource_uri)
+    };
+    if pending_uploads {
+        return Err(UserError("Wait until all scans are uploaded".to_string()).into());
+    }
+    let needs_attachment = matches!(
+        message.kind,
+        MessageKind::Post | MessageKind::Fax | MessageKind::Upload
+    );
+    if needs_attachment && client.get_attachments(foimessage_id).await?.is_empty() {
+        return Err(UserError("The message has no attachments yet".to_string()).into());
+    }
+
+    let message = client.publish_message(foimessage_id).await?;
+    if let Some(cache) = ApiCache::open(&app, &state)? {
+        update_cached_message(&app, &cache, &message);
+    }
+    Ok(message)
 }
 
 #[tauri::command(rename_all = "snake_case")]
 pub async fn get_foiattachments(
+    app: AppHandle,
     state: State<'_, Mutex<AppState>>,
     foimessage_id: FoiMessageId,
 ) -> Result<Vec<FoiAttachment>, AppError> {
-    let url = format!("{ATTACHMENT_ENDPOINT}?belongs_to={foimessage_id}");
-    let objects = get_all_objects(url, &state).await?;
-    Ok(objects)
+    let client = get_froide_client(&app, &state);
+    let key = format!("attachments/{foimessage_id}");
+    cached(
+        &app,
+        &state,
+        key,
+        "foiattachment-list",
+        move || async move { client.get_attachments(foimessage_id).await },
+    )
+    .await
 }
 
 #[tauri::command(rename_all = "snake_case")]
 pub async fn get_foiattachment(
+    app: AppHandle,
     state: State<'_, Mutex<AppState>>,
     foiattachment_id: FoiAttachmentId,
 ) -> Result<FoiAttachment, AppError> {
-    let client = get_api_client(&state)?;
-
-    let url = format!("{ATTACHMENT_ENDPOINT}{foiattachment_id}/");
-    let response = client.get(url).send().await?;
-    let api_response = response.json::<FoiAttachment>().await?;
-
-    Ok(api_response)
+    let client = get_froide_client(&app, &state);
+    let key = format!("attachment/{foiattachment_id}");
+    cached(
+        &app,
+        &state,
+        key,
+        "foiattachment-updated",
+        move || async move { client.get_attachment(foiattachment_id).await },
+    )
+    .await
 }
 
-pub fn get_tus_client(state: &State<'_, Mutex<AppState>>) -> Result<TusClient, AppError> {
-    let req_client = get_api_client(state)?;
-    Ok(TusClient::new(req_client))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub async fn create_upload(client: &TusClient, file_path: &Path) -> Result<String, AppError> {
-    let local: DateTime<Local> = Local::now();
-    let current_date = local.format("%d-%m-%Y").to_string();
+    fn filter(value: serde_json::Value) -> RequestFilter {
+        serde_json::from_value(value).unwrap()
+    }
 
-    let mut metadata = HashMap::new();
-    metadata.insert("filetype".to_string(), "application/pdf".to_string());
-    metadata.insert("filename".to_string(), format!("scan_{current_date}.pdf"));
+    #[test]
+    fn query_pairs_of_filter() {
+        let filter = filter(serde_json::json!({
+            "query": "  Akten ",
+            "status": "resolved",
+            "public_body": 2,
+            "jurisdiction": 3,
+            "created_after": "2025-01-01",
+            "created_before": "2025-02-01",
+            "ordering": "last_message",
+        }));
+        assert_eq!(
+            filter.query_pairs().unwrap(),
+            vec![
+                ("q", "Akten".to_string()),
+                ("status", "resolved".to_string()),
+                ("public_body", "2".to_string()),
+                ("jurisdiction", "3".to_string()),
+                ("created_at_after", "2025-01-01".to_string()),
+                ("created_at_before", "2025-02-01".to_string()),
+                ("ordering", "-last_message".to_string()),
+            ]
+        );
+    }
 
-    let upload_url = client
-        .create_with_metadata(UPLOAD_ENDPOINT, file_path, metadata)
-        .await?;
+    #[test]
+    fn query_pairs_skip_empty_filters() {
+        assert!(RequestFilter::default().query_pairs().unwrap().is_empty());
+        let filter = filter(serde_json::json!({"query": "   "}));
+        assert!(filter.query_pairs().unwrap().is_empty());
+    }
 
-    log::info!("Upload URL: {upload_url}");
-    // let upload_url = Url::parse(&upload_url).unwrap();
-    // let upload_url = match upload_url.host_str() {
-    //     Some(_) => upload_url.to_string(),
-    //     _ => {
-    //         format!("{}{}", UPLOAD_URL_BASE, upload_url)
-    //     }
-    // };
-    let upload_url = format!("{UPLOAD_URL_BASE}{upload_url}");
+    #[test]
+    fn query_pairs_reject_invalid_dates() {
+        let filter = filter(serde_json::json!({"created_after": "01.01.2025"}));
+        assert!(filter.query_pairs().is_err());
+    }
 
-    Ok(upload_url)
-}
+    fn request_with_status(status: FoiRequestStatus) -> FoiRequest {
+        let mut request: FoiRequest = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "resource_uri": "/api/v1/request/1/",
+            "url": "/a/1/",
+            "title": "Anfrage",
+            "created_at": "2025-01-01T00:00:00+01:00",
+            "last_message": "2025-01-02T00:00:00+01:00",
+            "public_body": {
+                "id": 2,
+                "name": "Behörde",
+                "resource_uri": "/api/v1/publicbody/2/",
+            },
+        }))
+        .unwrap();
+        request.status = status;
+        request
+    }
 
-pub async fn resume_upload(
-    client: &TusClient,
-    upload_url: &str,
-    file_path: &Path,
-) -> Result<bool, AppError> {
-    let response = client.upload(upload_url, file_path).await;
-    match response {
-        Ok(_) => Ok(true),
-        Err(e) => match e {
-            // Reset the upload state if the upload URL is not found
-            TusError::NotFoundError => Ok(false),
-            _ => Err(e.into()),
-        },
+    fn status_update(
+        status: FoiRequestStatus,
+        resolution: FoiRequestResolution,
+        costs: Option<f64>,
+    ) -> UpdateRequestStatus {
+        UpdateRequestStatus {
+            status,
+            resolution,
+            costs,
+        }
     }
-}
 
-#[derive(Serialize)]
-struct CreateAttachment {
-    message: String,
-    upload: String,
-}
+    #[test]
+    fn allowed_status_changes() {
+        let request = request_with_status(FoiRequestStatus::AwaitingResponse);
+        for update in [
+            status_update(
+                FoiRequestStatus::Resolved,
+                FoiRequestResolution::Successful,
+                Some(12.5),
+            ),
+            status_update(
+                FoiRequestStatus::Resolved,
+                FoiRequestResolution::Refused,
+                None,
+            ),
+            status_update(
+                FoiRequestStatus::AwaitingResponse,
+                FoiRequestResolution::Unresolved,
+                Some(0.0),
+            ),
+        ] {
+            assert!(update.validate(&request).is_ok(), "{update:?}");
+        }
+        let resolved = request_with_status(FoiRequestStatus::Resolved);
+        let reopen = status_update(
+            FoiRequestStatus::AwaitingResponse,
+            FoiRequestResolution::Unresolved,
+            None,
+        );
+        assert!(reopen.validate(&resolved).is_ok());
+    }
 
-pub async fn create_attachment(
-    state: &State<'_, Mutex<AppState>>,
-    message_resource_uri: String,
-    upload_url: &str,
-) -> Result<FoiAttachment, AppError> {
-    let client = get_api_client(state)?;
+    #[test]
+    fn rejected_status_changes() {
+        let request = request_with_status(FoiRequestStatus::AwaitingResponse);
+        for update in [
+            status_update(
+                FoiRequestStatus::Resolved,
+                FoiRequestResolution::Unresolved,
+                None,
+            ),
+            status_update(
+                FoiRequestStatus::AwaitingResponse,
+                FoiRequestResolution::Successful,
+                None,
+            ),
+            status_update(
+                FoiRequestStatus::Asleep,
+                FoiRequestResolution::Unresolved,
+                None,
+            ),
+            status_update(
+                FoiRequestStatus::Resolved,
+                FoiRequestResolution::Successful,
+                Some(-1.0),
+            ),
+            status_update(
+                FoiRequestStatus::Resolved,
+                FoiRequestResolution::Successful,
+                Some(f64::NAN),
+            ),
+        ] {
+            assert!(update.validate(&request).is_err(), "{update:?}");
+        }
+        let unsent = request_with_status(FoiRequestStatus::AwaitingUserConfirmation);
+        let resolve = status_update(
+            FoiRequestStatus::Resolved,
+            FoiRequestResolution::Successful,
+            None,
+        );
+        assert!(resolve.validate(&unsent).is_err());
+    }
 
-    log::info!("Message resource URI on attachment: {message_resource_uri}");
+    fn letter(kind: MessageKind) -> CreateMessage {
+        CreateMessage {
+            request: "/api/v1/request/1/".to_string(),
+            kind,
+            timestamp: "2025-01-01".to_string(),
+            is_response: true,
+            recipient_public_body: None,
+            sender_public_body: Some("/api/v1/publicbody/2/".to_string()),
+            subject: "Antwort".to_string(),
+            plaintext: String::new(),
+            registered_mail_date: None,
+        }
+    }
 
-    let att_data = CreateAttachment {
-        message: message_resource_uri,
-        upload: upload_url.to_string(),
-    };
+    #[test]
+    fn allowed_messages() {
+        let mut registered = letter(MessageKind::Post);
+        registered.registered_mail_date = Some("2025-01-01T10:00:00+01:00".to_string());
+        let mut registered_day = letter(MessageKind::Post);
+        registered_day.registered_mail_date = Some("2025-01-01".to_string());
+        let mut long_subject = letter(MessageKind::Post);
+        long_subject.subject = "ä".repeat(MAX_SUBJECT_LENGTH);
+        let mut upload = letter(MessageKind::Upload);
+        upload.sender_public_body = None;
+        let mut call = letter(MessageKind::Phone);
+        call.plaintext = "Rückruf vereinbart".to_string();
+        let mut sent = letter(MessageKind::Fax);
+        sent.is_response = false;
+        sent.sender_public_body = None;
+        sent.recipient_public_body = Some("/api/v1/publicbody/2/".to_string());
+        for message in [
+            letter(MessageKind::Post),
+            registered,
+            registered_day,
+            long_subject,
+            upload,
+            call,
+            sent,
+        ] {
+            assert!(message.validate().is_ok(), "{message:?}");
+        }
+    }
 
-    let response = client
-        .post(ATTACHMENT_ENDPOINT)
-        .json(&att_data)
-        .send()
-        .await?;
-    let response_text = response.text().await?;
-    log::info!("Response: {response_text}");
-    // let attachment = response.json::<FoiAttachment>().await?;
-    let attachment = serde_json::from_str::<FoiAttachment>(&response_text).unwrap();
-    Ok(attachment)
+    #[test]
+    fn rejected_messages() {
+        let mut missing_sender = letter(MessageKind::Post);
+        missing_sender.sender_public_body = None;
+        let mut missing_recipient = letter(MessageKind::Fax);
+        missing_recipient.is_response = false;
+        let mut registered_fax = letter(MessageKind::Fax);
+        registered_fax.registered_mail_date = Some("2025-01-01".to_string());
+        let mut invalid_date = letter(MessageKind::Post);
+        invalid_date.registered_mail_date = Some("gestern".to_string());
+        let mut long_subject = letter(MessageKind::Post);
+        long_subject.subject = "a".repeat(MAX_SUBJECT_LENGTH + 1);
+        let mut blank_summary = letter(MessageKind::Visit);
+        blank_summary.plaintext = "  ".to_string();
+        for message in [
+            letter(MessageKind::Email),
+            letter(MessageKind::Import),
+            letter(MessageKind::Phone),
+            blank_summary,
+            missing_sender,
+            missing_recipient,
+            registered_fax,
+            invalid_date,
+            long_subject,
+        ] {
+            assert!(message.validate().is_err(), "{message:?}");
+        }
+    }
 }
@@ -0,0 +1,142 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tauri::{AppHandle, Emitter, State, Wry};
+use tauri_plugin_store::{Store, StoreExt};
+
+use crate::error::AppError;
+use crate::{AppState, UserId};
+
+/// Payload of the events emitted when a cached value was revalidated.
+#[derive(Clone, Serialize)]
+struct CacheUpdate<T> {
+    key: String,
+    value: T,
+}
+
+/// Persistent cache of API objects of the logged in user.
+/// Keys look like `messages/{request_id}` or `attachment/{attachment_id}`.
+#[derive(Clone)]
+pub struct ApiCache {
+    store: Arc<Store<Wry>>,
+}
+
+fn cache_path(instance_id: &str, user_id: UserId) -> String {
+    format!("cache_{instance_id}_{user_id}.json")
+}
+
+impl ApiCache {
+    /// Open the cache of the current user, `None` if no user is known yet.
+    pub fn open(
+        app: &AppHandle,
+        state: &State<'_, Mutex<AppState>>,
+    ) -> Result<Option<Self>, AppError> {
+        let path = {
+            let state = state.lock().unwrap();
+            match state.user {
+                Some(ref user) => cache_path(&state.instance.id, user.id),
+                None => return Ok(None),
+            }
+        };
+        Ok(Some(ApiCache {
+            store: app.store(path)?,
+        }))
+    }
+
+    pub fn get<T>(&self, key: &str) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        let value = self.store.get(key)?;
+        match serde_json::from_value(value) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                // Written by an older version, fetch it again
+                log::warn!("Ignoring invalid cache entry {key}: {err}");
+                None
+            }
+        }
+    }
+
+    pub fn set<T>(&self, key: &str, value: &T)
+    where
+        T: Serialize,
+    {
+        match serde_json::to_value(value) {
+            Ok(value) => self.store.set(key, value),
+            Err(err) => {
+                log::warn!("Could not cache {key}: {err}");
+                return;
+            }
+        }
+        if let Err(err) = self.store.save() {
+            log::warn!("Could not save cache: {err}");
+        }
+    }
+
+    /// Cache a fresh value and emit it as `event`, so open views can update.
+    pub fn update<T>(&self, app: &AppHandle, key: &str, event: &str, value: T)
+    where
+        T: Serialize + Clone,
+    {
+        self.set(key, &value);
+        let update = CacheUpdate {
+            key: key.to_string(),
+            value,
+        };
+        if let Err(err) = app.emit(event, update) {
+            log::warn!("Could not emit {event}: {err}");
+        }
+    }
+
+    pub fn delete(&self, key: &str) {
+        self.store.delete(key);
+        if let Err(err) = self.store.save() {
+            log::warn!("Could not save cache: {err}");
+        }
+    }
+
+    /// Remove all cached objects of this user.
+    pub fn clear(&self) -> Result<(), AppError> {
+        self.store.clear();
+        self.store.save()?;
+        Ok(())
+    }
+}
+
+/// Stale-while-revalidate: return the cached value for `key` right away and fetch
+/// a fresh one in the background, which is cached and emitted as `event`.
+/// Without a cached value the fresh value is awaited and returned.
+pub async fn cached<T, F, Fut>(
+    app: &AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    key: String,
+    event: &'static str,
+    fetch: F,
+) -> Result<T, AppError>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + 'static,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, AppError>> + Send + 'static,
+{
+    let Some(cache) = ApiCache::open(app, state)? else {
+        return fetch().await;
+    };
+    let Some(value) = cache.get::<T>(&key) else {
+        let value = fetch().await?;
+        cache.set(&key, &value);
+        return Ok(value);
+    };
+
+    let app = app.clone();
+    let fresh_value = fetch();
+    tauri::async_runtime::spawn(async move {
+        match fresh_value.await {
+            Ok(value) => cache.update(&app, &key, event, value),
+            Err(err) => log::warn!("Could not revalidate {key}: {err}"),
+        }
+    });
+    Ok(value)
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use futures_util::future::{BoxFuture, try_join_all};
+use oauth2::url::Url;
+use reqwest::{StatusCode, header};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::User;
+use crate::api::{
+    ApiResponse, CreateMessage, FoiAttachment, FoiAttachmentId, FoiMessage, FoiMessageId,
+    FoiRequest, FoiRequestId, MessageKind, PublicBody, PublicBodyFilter, RequestFilter,
+    UpdateDraftMessage, UpdateRequestStatus,
+};
+use crate::error::{AppError, AuthorizationError, TusError, UserError};
+use crate::instance::FroideInstance;
+use crate::queue::PartialUpload;
+use crate::tus::{
+    CreatedUpload, ServerInfo, TusClient, TusExtension, UploadProgress, earliest_expiry,
+    partial_ranges,
+};
+
+const USER_ENDPOINT: &str = "user/";
+const REQUEST_ENDPOINT: &str = "request/";
+const REQUEST_SEARCH_ENDPOINT: &str = "request/search/";
+const MESSAGE_ENDPOINT: &str = "message/";
+const UPLOAD_ENDPOINT: &str = "upload/";
+const ATTACHMENT_ENDPOINT: &str = "attachment/";
+const PUBLIC_BODY_ENDPOINT: &str = "publicbody/";
+
+// Chunk sizes between which uploads adapt to the connection
+const MIN_UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+const MAX_UPLOAD_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+// Larger files are split into parallel partial uploads if the server supports it
+const PARALLEL_UPLOAD_MIN_SIZE: u64 = 8 * 1024 * 1024;
+const PARALLEL_UPLOAD_PARTS: usize = 4;
+
+/// Supplies the access token for API requests, so the client does not
+/// depend on where the token is stored.
+pub trait TokenProvider: Send + Sync {
+    fn access_token(&self) -> Result<String, AppError>;
+
+    /// Get a new access token after `rejected_token` was rejected by the server.
+    /// Nothing is done if the token was already replaced in the meantime.
+    fn refresh<'a>(&'a self, rejected_token: &'a str) -> BoxFuture<'a, Result<(), AppError>>;
+}
+
+/// Client for the API of a Froide instance.
+/// Cloning is cheap, all clones share the connection pool.
+#[derive(Clone)]
+pub struct FroideClient {
+    instance: FroideInstance,
+    http: reqwest::Client,
+    tokens: Arc<dyn TokenProvider>,
+}
+
+#[derive(Serialize)]
+struct CreateAttachment<'a> {
+    message: &'a str,
+    upload: &'a str,
+}
+
+impl FroideClient {
+    pub fn new(
+        instance: FroideInstance,
+        http: reqwest::Client,
+        tokens: Arc<dyn TokenProvider>,
+    ) -> Self {
+        FroideClient {
+            instance,
+            http,
+            tokens,
+        }
+    }
+
+    fn auth_headers(&self, access_token: &str) -> Result<header::HeaderMap, AppError> {
+        let mut auth_value = header::HeaderValue::from_str(&format!("Bearer {access_token}"))
+            .map_err(|_| AuthorizationError("Invalid access token".to_string()))?;
+        auth_value.set_sensitive(true);
+
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            header::HeaderValue::from_static("application/json"),
+        );
+        headers.insert(header::AUTHORIZATION, auth_value);
+        Ok(headers)
+    }
+
+    /// Send the request, if the access token is rejected refresh it once and send it again.
+    async fn send_request(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, AppError> {
+        let replay = request.try_clone();
+        let access_token = self.tokens.access_token()?;
+        let mut response = request
+            .headers(self.auth_headers(&access_token)?)
+            .send()
+            .await?;
+        if response.status() == StatusCode::UNAUTHORIZED
+            && let Some(replay) = replay
+        {
+            log::info!("Access token was rejected, refreshing");
+            self.tokens.refresh(&access_token).await?;
+            let access_token = self.tokens.access_token()?;
+            response = replay
+                .headers(self.auth_headers(&access_token)?)
+                .send()
+                .await?;
+        }
+        if response.status() == StatusCode::BAD_REQUEST {
+            // Validation errors of the submitted data, e.g. `{"costs": ["..."]}`
+            let details = response.text().await.unwrap_or_default();
+            return Err(UserError(format!("The server rejected the data: {details}")).into());
+        }
+        Ok(response.error_for_status()?)
+    }
+
+    async fn send<T>(&self, request: reqwest::RequestBuilder) -> Result<T, AppError>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(self.send_request(request).await?.json::<T>().await?)
+    }
+
+    /// Run a tus operation, if the access token is rejected refresh it once and run it again.
+    /// Uploads continue from the offset the server confirmed, so repeating them is safe.
+    async fn with_tus_auth<T, F, Fut>(
+        &self,
+        tus_client: &TusClient,
+        operation: F,
+    ) -> Result<T, AppError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, TusError>>,
+    {
+        // Send the latest token, it may have been refreshed since the tus client was created
+        let access_token = self.tokens.access_token()?;
+        tus_client.set_headers(self.auth_headers(&access_token)?);
+        match operation().await {
+            Err(TusError::UnexpectedStatusCode(401)) => {
+                log::info!("Access token was rejected by upload endpoint, refreshing");
+                self.tokens.refresh(&access_token).await?;
+                let access_token = self.tokens.access_token()?;
+                tus_client.set_headers(self.auth_headers(&access_token)?);
+                Ok(operation().await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn get<T>(&self, url: &str) -> Result<T, AppError>
+    where
+        T: DeserializeOwned,
+    {
+        self.send(self.http.get(url)).await
+    }
+
+    async fn post<B, T>(&self, url: &str, body: &B) -> Result<T, AppError>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        self.send(self.http.post(url).json(body)).await
+    }
+
+    async fn patch<B, T>(&self, url: &str, body: &B) -> Result<T, AppError>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        self.send(self.http.patch(url).json(body)).await
+    }
+
+    async fn delete(&self, url: &str) -> Result<(), AppError> {
+        self.send_request(self.http.delete(url)).await?;
+        Ok(())
+    }
+
+    /// Get a single page of a list endpoint, `url` may be a `next` link of a previous page.
+    pub async fn get_page<T>(&self, url: &str) -> Result<ApiResponse<T>, AppError>
+    where
+        T: DeserializeOwned,
+    {
+        self.get(url).await
+    }
+
+    /// Follow the `next` links of a list endpoint and collect all objects.
+    pub async fn get_all<T>(&self, url: String) -> Result<Vec<T>, AppError>
+    where
+        T: DeserializeOwned,
+    {
+        let mut objects: Vec<T> = vec![];
+        let mut next = Some(url);
+
+        while let Some(ref next_url) = next {
+            let api_response = self.get_page::<T>(next_url).await?;
+            objects.extend(api_response.objects);
+            next = api_response.meta.next;
+        }
+        Ok(objects)
+    }
+
+    pub async fn get_user(&self) -> Result<User, AppError> {
+        self.get(&self.instance.api_url(USER_ENDPOINT)).await
+    }
+
+    /// URL of the first page of requests made by the given user.
+    pub fn requests_url(&self, user_id: crate::UserId) -> String {
+        let endpoint = self.instance.api_url(REQUEST_ENDPOINT);
+        format!("{endpoint}?user={user_id}")
+    }
+
+    /// URL of the first page of requests of the given user with messages after `since`
+    /// (ISO 8601), most recently changed first.
+    pub fn changed_requests_url(&self, user_id: crate::UserId, since: &str) -> String {
+        let endpoint = self.instance.api_url(REQUEST_ENDPOINT);
+        format!("{endpoint}?user={user_id}&last_message_after={since}&ordering=-last_message")
+    }
+
+    /// A page of the requests of the given user matching the filter.
+    /// Full text queries use the search endpoint, which accepts the same filters.
+    pub async fn search_requests(
+        &self,
+        user_id: crate::UserId,
+        filter: &RequestFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<ApiResponse<FoiRequest>, AppError> {
+        let endpoint = match filter.query() {
+            Some(_) => REQUEST_SEARCH_ENDPOINT,
+            None => REQUEST_ENDPOINT,
+        };
+        let mut url = Url::parse(&self.instance.api_url(endpoint))?;
+        url.query_pairs_mut()
+            .append_pair("user", &user_id.to_string())
+            .extend_pairs(filter.query_pairs()?)
+            .append_pair("offset", &offset.to_string())
+            .append_pair("limit", &limit.to_string());
+        self.get_page(url.as_str()).await
+    }
+
+    /// A page of the public bodies matching the filter, ordered by name.
+    pub async fn search_public_bodies(
+        &self,
+        filter: &PublicBodyFilter,
+        offset: u32,
+        limit: u32,
+    ) -> Result<ApiResponse<PublicBody>, AppError> {
+        let mut url = Url::parse(&self.instance.api_url(PUBLIC_BODY_ENDPOINT))?;
+        url.query_pairs_mut()
+            .extend_pairs(filter.query_pairs())
+            .append_pair("offset", &offset.to_string())
+            .append_pair("limit", &limit.to_string());
+        self.get_page(url.as_str()).await
+    }
+
+    pub async fn get_request(&self, request_id: FoiRequestId) -> Result<FoiRequest, AppError> {
+        let endpoint = self.instance.api_url(REQUEST_ENDPOINT);
+        self.get(&format!("{endpoint}{request_id}/")).await
+    }
+
+    /// Change status, resolution or costs of a request of the user.
+    pub async fn update_request(
+        &self,
+        request_id: FoiRequestId,
+        update: &UpdateRequestStatus,
+    ) -> Result<FoiRequest, AppError> {
+        let endpoint = self.instance.api_url(REQUEST_ENDPOINT);
+        self.patch(&format!("{endpoint}{request_id}/"), update)
+            .await
+    }
+
+    /// Messages of the given kinds of a request, newest first.
+    /// The API filters by a single kind, so each kind is fetched with its own request.
+    pub async fn get_messages(
+        &self,
+        request_id: FoiRequestId,
+        kinds: &[MessageKind],
+    ) -> Result<Vec<FoiMessage>, AppError> {
+        let endpoint = self.instance.api_url(MESSAGE_ENDPOINT);
+        let pages = try_join_all(kinds.iter().filter_map(|kind| kind.api_name()).map(|kind| {
+            self.get_all::<FoiMessage>(format!("{endpoint}?request={request_id}&kind={kind}"))
+        }))
+        .await?;
+        let mut messages: Vec<FoiMessage> = pages.into_iter().flatten().collect();
+        messages.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(messages)
+    }
+
+    pub async fn get_message(&self, message_id: FoiMessageId) -> Result<FoiMessage, AppError> {
+        let endpoint = self.instance.api_url(MESSAGE_ENDPOINT);
+        self.get(&format!("{endpoint}{message_id}/")).await
+    }
+
+    pub async fn create_message(&self, message: &CreateMessage) -> Result<FoiMessage, AppError> {
+        self.post(&self.instance.api_url(MESSAGE_ENDPOINT), message)
+            .await
+    }
+
+    /// Change the date, public bodies or subject of a draft message.
+    pub async fn update_message(
+        &self,
+        message_id: FoiMessageId,
+        update: &UpdateDraftMessage,
+    ) -> Result<FoiMessage, AppError> {
+        let endpoint = self.instance.api_url(MESSAGE_ENDPOINT);
+        self.patch(&format!("{endpoint}{message_id}/"), update)
+            .await
+    }
+
+    /// Delete a draft message together with its attachments.
+    pub async fn delete_message(&self, message_id: FoiMessageId) -> Result<(), AppError> {
+        let endpoint = self.instance.api_url(MESSAGE_ENDPOINT);
+        self.delete(&format!("{endpoint}{message_id}/")).await
+    }
+
+    /// Publish a draft message, which makes it part of the request.
+    pub async fn publish_message(&self, message_id: FoiMessageId) -> Result<FoiMessage, AppError> {
+        let endpoint = self.instance.api_url(MESSAGE_ENDPOINT);
+        self.post(
+            &format!("{endpoint}{message_id}/publish/"),
+            &serde_json::json!({}),
+        )
+        .await
+    }
+
+    pub async fn get_attachments(
+        &self,
+        message_id: FoiMessageId,
+    ) -> Result<Vec<FoiAttachment>, AppError> {
+        let endpoint = self.instance.api_url(ATTACHMENT_ENDPOINT);
+        self.get_all(format!("{endpoint}?belongs_to={message_id}"))
+            .await
+    }
+
+    pub async fn get_attachment(
+        &self,
+        attachment_id: FoiAttachmentId,
+    ) -> Result<FoiAttachment, AppError> {
+        let endpoint = self.instance.api_url(ATTACHMENT_ENDPOINT);
+        self.get(&format!("{endpoint}{attachment_id}/")).await
+    }
+
+    /// Attach a finished upload to a message.
+    pub async fn create_attachment(
+        &self,
+        message_resource_uri: &str,
+        upload_url: &str,
+    ) -> Result<FoiAttachment, AppError> {
+        log::info!("Message resource URI on attachment: {message_resource_uri}");
+
+        let att_data = CreateAttachment {
+            message: message_resource_uri,
+            upload: upload_url,
+        };
+        self.post(&self.instance.api_url(ATTACHMENT_ENDPOINT), &att_data)
+            .await
+    }
+
+    /// A tus client for the upload endpoint, configured for the extensions the server supports.
+    pub async fn tus_client(&self) -> Result<TusClient, AppError> {
+        let client = TusClient::new(self.http.clone())
+            .with_chunk_size_limits(MIN_UPLOAD_CHUNK_SIZE, MAX_UPLOAD_CHUNK_SIZE);
+        let upload_endpoint = self.instance.api_url(UPLOAD_ENDPOINT);
+        let server_info = match self
+            .with_tus_auth(&client, || client.get_server_info(&upload_endpoint))
+            .await
+        {
+            Ok(server_info) => server_info,
+            Err(AppError::TusError(TusError::UnexpectedStatusCode(status))) => {
+                // Fall back to the core protocol without optional extensions
+                log::warn!("Could not get tus server info, status code {status}");
+                ServerInfo::default()
+            }
+            Err(e) => return Err(e),
+        };
+        log::info!("tus server info: {server_info:?}");
+        if !server_info.supports_client_version() {
+            return Err(TusError::UnsupportedVersion(server_info.supported_versions).into());
+        }
+        Ok(client.with_server_info(server_info))
+    }
+
+    pub async fn create_upload(
+        &self,
+        tus_client: &TusClient,
+        file_path: &Path,
+    ) -> Result<CreatedUpload, AppError> {
+        let upload_endpoint = self.instance.api_url(UPLOAD_ENDPOINT);
+        let upload = self
+            .with_tus_auth(tus_client, || {
+                tus_client.create_with_metadata(&upload_endpoint, file_path, upload_metadata())
+            })
+            .await?;
+
+        log::info!("Upload URL: {}", upload.location);
+        Ok(CreatedUpload {
+            location: tus_client.resolve_location(&upload_endpoint, &upload.location),
+            ..upload
+        })
+    }
+
+    /// Whether the file should be split into partial uploads sent in parallel.
+    pub fn use_parallel_upload(
+        &self,
+        tus_client: &TusClient,
+        file_path: &Path,
+    ) -> Result<bool, AppError> {
+        Ok(tus_client.supports(TusExtension::Concatenation)
+            && file_path.metadata()?.len() >= PARALLEL_UPLOAD_MIN_SIZE)
+    }
+
+    /// Create the partial uploads the file is split into, so they can be sent in parallel.
+    /// Also returns when the first of them expires.
+    pub async fn create_partial_uploads(
+        &self,
+        tus_client: &TusClient,
+        file_path: &Path,
+    ) -> Result<(Vec<PartialUpload>, Option<DateTime<FixedOffset>>), AppError> {
+        let upload_endpoint = self.instance.api_url(UPLOAD_ENDPOINT);
+        let file_len = file_path.metadata()?.len() as usize;
+        tus_client.check_max_upload_size(file_len)?;
+
+        let mut parts = vec![];
+        let mut expires_at: Option<DateTime<FixedOffset>> = None;
+        for (start, len) in partial_ranges(file_len, PARALLEL_UPLOAD_PARTS) {
+            let partial = self
+                .with_tus_auth(tus_client, || {
+                    tus_client.create_partial(&upload_endpoint, len)
+                })
+                .await;
+            match partial {
+                Ok(partial) => {
+                    expires_at = earliest_expiry(expires_at, partial.expires_at);
+                    parts.push(PartialUpload {
+                        upload_url: tus_client
+                            .resolve_location(&upload_endpoint, &partial.location),
+                        location: partial.location,
+                        start,
+                        len,
+                        offset: 0,
+                    });
+                }
+                Err(e) => {
+                    // Don't leave the parts created so far behind
+                    let upload_urls: Vec<_> =
+                        parts.iter().map(|part| part.upload_url.as_str()).collect();
+                    self.discard_uploads(tus_client, &upload_urls).await;
+                    return Err(e);
+                }
+            }
+        }
+        log::info!("Created {} partial uploads", parts.len());
+        Ok((parts, expires_at))
+    }
+
+    /// Continue a partial upload, returns `false` if it does not exist on the server anymore.
+    pub async fn resume_partial_upload<F>(
+        &self,
+        tus_client: &TusClient,
+        part: &PartialUpload,
+        file_path: &Path,
+        on_progress: F,
+    ) -> Result<bool, AppError>
+    where
+        F: Fn(UploadProgress),
+    {
+        let response = self
+            .with_tus_auth(tus_client, || {
+                tus_client.upload_partial(
+                    &part.upload_url,
+                    file_path,
+                    part.start,
+                    part.len,
+                    &on_progress,
+                )
+            })
+            .await;
+        match response {
+            Ok(_) => Ok(true),
+            Err(AppError::TusError(TusError::NotFoundError)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Concatenate the complete partial uploads of a file into a single upload.
+    pub async fn concatenate_uploads(
+        &self,
+        tus_client: &TusClient,
+        parts: &[PartialUpload],
+    ) -> Result<CreatedUpload, AppError> {
+        let upload_endpoint = self.instance.api_url(UPLOAD_ENDPOINT);
+        let locations: Vec<_> = parts.iter().map(|part| part.location.as_str()).collect();
+        let upload = self
+            .with_tus_auth(tus_client, || {
+                tus_client.concatenate(&upload_endpoint, &locations, upload_metadata())
+            })
+            .await?;
+
+        log::info!("Upload URL: {}", upload.location);
+        Ok(CreatedUpload {
+            location: tus_client.resolve_location(&upload_endpoint, &upload.location),
+            ..upload
+        })
+    }
+
+    /// Continue an upload, returns `false` if the upload does not exist on the server anymore.
+    /// Asks the server for the offset unless it is known, e.g. from the creation request.
+    pub async fn resume_upload<F>(
+        &self,
+        tus_client: &TusClient,
+        upload_url: &str,
+        offset: Option<usize>,
+        file_path: &Path,
+        on_progress: F,
+    ) -> Result<bool, AppError>
+    where
+        F: Fn(UploadProgress),
+    {
+        let response = self
+            .with_tus_auth(tus_client, || async {
+                match offset {
+                    Some(offset) => {
+                        tus_client
+                            .upload_from(upload_url, file_path, offset, &on_progress)
+                            .await
+                    }
+                    None => tus_client.upload(upload_url, file_path, &on_progress).await,
+                }
+            })
+            .await;
+        match response {
+            Ok(_) => Ok(true),
+            Err(e) => match e {
+                // Reset the upload state if the upload URL is not found
+                AppError::TusError(TusError::NotFoundError) => Ok(false),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Delete an upload on the server. Uploads that do not exist anymore
+    /// or servers without the termination extension are only logged.
+    pub async fn terminate_upload(
+        &self,
+        tus_client: &TusClient,
+        upload_url: &str,
+    ) -> Result<(), AppError> {
+        match self
+            .with_tus_auth(tus_client, || tus_client.terminate(upload_url))
+            .await
+        {
+            Ok(()) => {
+                log::info!("Terminated upload {upload_url}");
+                Ok(())
+            }
+            Err(AppError::TusError(TusError::NotFoundError)) => {
+                log::info!("Upload {upload_url} does not exist anymore");
+                Ok(())
+            }
+            Err(AppError::TusError(TusError::UnsupportedExtension(extension))) => {
+                log::warn!(
+                    "Server does not support {extension:?}, upload {upload_url} will expire"
+                );
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete uploads that are not needed anymore, failures are only logged
+    /// as the server removes unfinished uploads once they expire.
+    pub async fn discard_uploads(&self, tus_client: &TusClient, upload_urls: &[&str]) {
+        for upload_url in upload_urls {
+            if let Err(err) = self.terminate_upload(tus_client, upload_url).await {
+                log::warn!("Could not terminate upload {upload_url}: {err}");
+            }
+        }
+    }
+}
+
+fn upload_metadata() -> HashMap<String, String> {
+    let local: DateTime<Local> = Local::now();
+    let current_date = local.format("%d-%m-%Y").to_string();
+
+    let mut metadata = HashMap::new();
+    metadata.insert("filetype".to_string(), "application/pdf".to_string());
+    metadata.insert("filename".to_string(), format!("scan_{current_date}.pdf"));
+    metadata
+}
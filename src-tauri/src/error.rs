@@ -7,6 +7,8 @@ use std::{
 
 use thiserror::Error;
 
+use crate::tus::TusExtension;
+
 #[derive(Error, Debug)]
 #[error("{0}")]
 pub struct AuthorizationError(pub String);
@@ -37,6 +39,12 @@ pub enum TusError {
     WrongUploadOffsetError,
     /// The specified file is larger that what is supported by the server.
     FileTooLarge,
+    /// The server does not support the protocol version of the client.
+    UnsupportedVersion(Vec<String>),
+    /// The server does not support an extension required for the operation.
+    UnsupportedExtension(TusExtension),
+    /// The server repeatedly reported that the checksum of a chunk did not match.
+    ChecksumMismatch,
     RequestError(reqwest::Error),
 }
 
@@ -52,6 +60,9 @@ impl Display for TusError {
             TusError::FileReadError => "Unable to read the specified file".to_string(),
             TusError::WrongUploadOffsetError => "The client tried to upload the file with an incorrect offset".to_string(),
             TusError::FileTooLarge => "The specified file is larger that what is supported by the server".to_string(),
+            TusError::UnsupportedVersion(versions) => format!("The server only supports the protocol versions: {}", versions.join(", ")),
+            TusError::UnsupportedExtension(extension) => format!("The server does not support the {extension:?} extension"),
+            TusError::ChecksumMismatch => "The checksum of the uploaded data did not match the checksum calculated by the server".to_string(),
             TusError::RequestError(error) => format!("Error during HTTP request: {error}"),
             TusError::HeaderParsingError(error) => format!("Could not convert header to str: {error}"),
         };
@@ -64,6 +75,21 @@ impl Display for TusError {
 
 impl StdError for TusError {}
 
+impl TusError {
+    /// Whether the error is likely temporary, so the request can be tried again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            TusError::UnexpectedStatusCode(status_code) => {
+                *status_code >= 500 || *status_code == 408 || *status_code == 429
+            }
+            TusError::RequestError(error) => {
+                error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<io::Error> for TusError {
     fn from(e: io::Error) -> Self {
         TusError::IoError(e)
@@ -116,4 +142,19 @@ pub enum AppError {
     TusError(#[from] TusError),
     #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
+    #[error("Could not reach server: {0}")]
+    ConnectionError(String),
+}
+
+impl AppError {
+    /// Whether the server could not be reached, e.g. because the device is offline.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            AppError::RequestError(error) | AppError::TusError(TusError::RequestError(error)) => {
+                error.is_connect() || error.is_timeout()
+            }
+            AppError::ConnectionError(_) => true,
+            _ => false,
+        }
+    }
 }
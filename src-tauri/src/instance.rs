@@ -0,0 +1,175 @@
+use std::sync::Mutex;
+
+use oauth2::url::Url;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::AppState;
+use crate::error::{AppError, UserError};
+
+const FRAGDENSTAAT_ID: &str = "fragdenstaat";
+const FRAGDENSTAAT_NAME: &str = "FragDenStaat";
+const FRAGDENSTAAT_BASE_URL: &str = "https://fragdenstaat.de";
+const FRAGDENSTAAT_CLIENT_ID: &str = "1nmNtPIiQ7xA1yqzZDwEmOlguNEhdnp5vQpGyfSd";
+
+/// A Froide deployment the app can talk to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FroideInstance {
+    pub id: String,
+    pub name: String,
+    /// Base URL without trailing slash, e.g. `https://fragdenstaat.de`
+    pub base_url: String,
+    /// OAuth client id registered for the app on this instance
+    pub client_id: String,
+    /// Hosts that may be used as login start URL
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for FroideInstance {
+    fn default() -> Self {
+        FroideInstance {
+            id: FRAGDENSTAAT_ID.to_string(),
+            name: FRAGDENSTAAT_NAME.to_string(),
+            base_url: FRAGDENSTAAT_BASE_URL.to_string(),
+            client_id: FRAGDENSTAAT_CLIENT_ID.to_string(),
+            allowed_hosts: vec!["fragdenstaat.de".to_string()],
+        }
+    }
+}
+
+impl FroideInstance {
+    /// Absolute URL for a path on this instance, `path` starts with a slash.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    /// Absolute URL of an API v1 endpoint, e.g. `api_url("request/")`.
+    pub fn api_url(&self, endpoint: &str) -> String {
+        self.url(&format!("/api/v1/{endpoint}"))
+    }
+
+    pub fn is_allowed_host(&self, host: Option<&str>) -> bool {
+        match host {
+            Some(host) => self.allowed_hosts.iter().any(|allowed| allowed == host),
+            None => false,
+        }
+    }
+
+    /// Check and normalize a user provided instance configuration.
+    pub fn validate(mut self) -> Result<Self, AppError> {
+        // The id is part of the cache file name
+        let valid_id = !self.id.is_empty()
+            && self
+                .id
+                .chars()
+                .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-'));
+        if !valid_id {
+            return Err(UserError(format!("Invalid instance id: {}", self.id)).into());
+        }
+        let base_url = Url::parse(&self.base_url)?;
+        // Tokens must not be sent in plaintext, local development servers excepted
+        let secure =
+            base_url.scheme() == "https" || (cfg!(debug_assertions) && base_url.scheme() == "http");
+        if !secure {
+            return Err(UserError(format!("Invalid instance URL: {}", self.base_url)).into());
+        }
+        let host = match base_url.host_str() {
+            Some(host) => host.to_string(),
+            None => {
+                return Err(UserError(format!("Invalid instance URL: {}", self.base_url)).into());
+            }
+        };
+        if self.client_id.is_empty() {
+            return Err(UserError("Missing OAuth client id".to_string()).into());
+        }
+        self.base_url = self.base_url.trim_end_matches('/').to_string();
+        // Instances with the same id would share their cache
+        if let Some(known) = known_instances()
+            .into_iter()
+            .find(|known| known.id == self.id && known.base_url != self.base_url)
+        {
+            return Err(UserError(format!(
+                "The instance id {} is already used by {}",
+                self.id, known.name
+            ))
+            .into());
+        }
+        if !self.allowed_hosts.contains(&host) {
+            self.allowed_hosts.push(host);
+        }
+        Ok(self)
+    }
+}
+
+/// Instances that can be selected without entering a configuration.
+pub fn known_instances() -> Vec<FroideInstance> {
+    vec![FroideInstance::default()]
+}
+
+pub fn get_instance(state: &State<'_, Mutex<AppState>>) -> FroideInstance {
+    let state = state.lock().unwrap();
+    state.instance.clone()
+}
+
+#[tauri::command]
+pub async fn get_instances(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<Vec<FroideInstance>, AppError> {
+    let current = get_instance(&state);
+    let mut instances = known_instances();
+    if !instances.contains(&current) {
+        instances.push(current);
+    }
+    Ok(instances)
+}
+
+#[tauri::command]
+pub async fn get_current_instance(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<FroideInstance, AppError> {
+    Ok(get_instance(&state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(base_url: &str) -> FroideInstance {
+        FroideInstance {
+            id: "example".to_string(),
+            name: "Example".to_string(),
+            base_url: base_url.to_string(),
+            client_id: "client".to_string(),
+            allowed_hosts: vec![],
+        }
+    }
+
+    #[test]
+    fn urls_of_instance() {
+        let instance = FroideInstance::default();
+        assert_eq!(
+            instance.api_url("request/"),
+            "https://fragdenstaat.de/api/v1/request/"
+        );
+        assert_eq!(instance.url("/a/1/"), "https://fragdenstaat.de/a/1/");
+        assert!(instance.is_allowed_host(Some("fragdenstaat.de")));
+        assert!(!instance.is_allowed_host(Some("example.org")));
+        assert!(!instance.is_allowed_host(None));
+    }
+
+    #[test]
+    fn validate_normalizes_instance() {
+        let instance = instance("https://froide.example.org/").validate().unwrap();
+        assert_eq!(instance.base_url, "https://froide.example.org");
+        assert_eq!(instance.allowed_hosts, vec!["froide.example.org"]);
+    }
+
+    #[test]
+    fn validate_rejects_invalid_instances() {
+        assert!(instance("froide.example.org").validate().is_err());
+        assert!(instance("ftp://froide.example.org").validate().is_err());
+        let mut without_client_id = instance("https://froide.example.org");
+        without_client_id.client_id = String::new();
+        assert!(without_client_id.validate().is_err());
+    }
+}
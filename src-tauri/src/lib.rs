@@ -1,17 +1,29 @@
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 mod account;
 mod api;
+mod cache;
+mod client;
 mod error;
+mod instance;
+mod queue;
 mod scan;
+mod sync;
 mod tus;
 
-use account::{get_user, logout, start_oauth};
+use account::{TokenRefresh, get_user, logout, start_oauth};
 use api::{
-    create_foimessage, get_foiattachment, get_foiattachments, get_foimessage, get_foimessages,
-    get_foirequest, get_foirequests,
+    create_foimessage, delete_foimessage, get_foiattachment, get_foiattachments, get_foimessage,
+    get_foimessages, get_foirequest, get_foirequests, publish_foimessage, search_foirequests,
+    search_publicbodies, update_foimessage, update_foirequest_status,
 };
-use scan::{scan_document, upload_document};
-use std::sync::Mutex;
+use instance::{FroideInstance, get_current_instance, get_instances};
+use queue::{UploadJob, UploadStage};
+use scan::{
+    cancel_upload, discard_upload_job, list_upload_jobs, retry_upload_job, scan_document,
+    upload_document,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tauri::Manager;
 use tauri_plugin_store::StoreExt;
 
@@ -39,13 +51,28 @@ struct User {
 struct AppState {
     auth: Option<AuthState>,
     user: Option<User>,
-    message_resource_uri: Option<String>,
-    file_path: Option<String>,
-    upload_url: Option<String>,
+    instance: FroideInstance,
+    upload_queue: Vec<UploadJob>,
+    /// The job currently being uploaded
+    active_upload_job: Option<queue::UploadJobId>,
+    /// Shared by all API clients to reuse connections
+    http_client: reqwest::Client,
+    token_refresh: Arc<tokio::sync::Mutex<TokenRefresh>>,
 }
 
 const STORE_PATH: &str = "store.bin";
 
+/// How long to wait for a connection, so an unreachable server is noticed quickly.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        // Like `reqwest::Client::new`, this only fails if TLS cannot be initialized
+        .expect("Could not create HTTP client")
+}
+
 impl AppState {
     fn load(app_handle: &tauri::AppHandle) -> Result<Self, tauri_plugin_store::Error> {
         let store = app_handle.store(STORE_PATH)?;
@@ -58,18 +85,47 @@ impl AppState {
                 .map(|v| v.as_str().unwrap().to_string()),
             expires_at: store.get("expires_at").map(|v| v.as_u64().unwrap()),
         });
+        let mut upload_queue: Vec<UploadJob> = store
+            .get("upload_queue")
+            .and_then(|v| serde_json::from_value(v).ok())
+            .unwrap_or_default();
+
+        // Migrate the single pending upload of older versions into the queue
+        let legacy_message_resource_uri = store.get("message_resource_uri");
+        let legacy_file_path = store.get("file_path");
+        if let (Some(message_resource_uri), Some(file_path)) =
+            (legacy_message_resource_uri, legacy_file_path)
+        {
+            let mut job = UploadJob::new(
+                queue::new_job_id(),
+                file_path.as_str().unwrap().to_string(),
+                message_resource_uri.as_str().unwrap().to_string(),
+            );
+            if let Some(upload_url) = store.get("upload_url") {
+                job.stage = UploadStage::UploadCreated {
+                    upload_url: upload_url.as_str().unwrap().to_string(),
+                };
+            }
+            upload_queue.push(job);
+        }
+
+        let user = store
+            .get("user")
+            .and_then(|v| serde_json::from_value(v).ok());
+
+        let instance = store
+            .get("instance")
+            .and_then(|v| serde_json::from_value(v).ok())
+            .unwrap_or_default();
+
         Ok(AppState {
             auth: auth_state,
-            user: None,
-            message_resource_uri: store
-                .get("message_resource_uri")
-                .map(|v| v.as_str().unwrap().to_string()),
-            file_path: store
-                .get("file_path")
-                .map(|v| v.as_str().unwrap().to_string()),
-            upload_url: store
-                .get("upload_url")
-                .map(|v| v.as_str().unwrap().to_string()),
+            user,
+            instance,
+            upload_queue,
+            active_upload_job: None,
+            http_client: http_client(),
+            token_refresh: Default::default(),
         })
     }
 
@@ -94,22 +150,25 @@ impl AppState {
                 store.delete("expires_at");
             }
         }
-        if let Some(ref message_resource_uri) = self.message_resource_uri {
-            store.set("message_resource_uri", message_resource_uri.clone());
-        } else {
-            store.delete("message_resource_uri");
-        }
-
-        if let Some(ref file_path) = self.file_path {
-            store.set("file_path", file_path.clone());
-        } else {
-            store.delete("file_path");
-        }
-        if let Some(ref upload_url) = self.upload_url {
-            store.set("upload_url", upload_url.clone());
-        } else {
-            store.delete("upload_url");
+        // Kept to start offline with the user of the last session
+        match self.user {
+            Some(ref user) => store.set("user", serde_json::to_value(user).unwrap_or_default()),
+            None => {
+                store.delete("user");
+            }
         }
+        store.set(
+            "instance",
+            serde_json::to_value(&self.instance).unwrap_or_default(),
+        );
+        store.set(
+            "upload_queue",
+            serde_json::to_value(&self.upload_queue).unwrap_or_default(),
+        );
+        // Superseded by upload_queue
+        store.delete("message_resource_uri");
+        store.delete("file_path");
+        store.delete("upload_url");
 
         store.save()?;
         Ok(())
@@ -145,15 +204,27 @@ pub fn run() {
             get_user,
             start_oauth,
             logout,
+            get_instances,
+            get_current_instance,
             get_foirequests,
             get_foirequest,
+            search_foirequests,
+            update_foirequest_status,
+            search_publicbodies,
             get_foimessages,
             get_foimessage,
             create_foimessage,
+            update_foimessage,
+            delete_foimessage,
+            publish_foimessage,
             get_foiattachments,
             get_foiattachment,
             scan_document,
             upload_document,
+            list_upload_jobs,
+            retry_upload_job,
+            discard_upload_job,
+            cancel_upload,
         ])
         .setup(|app| {
             #[cfg(mobile)]
@@ -0,0 +1,216 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::TimeDelta;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::AppState;
+use crate::api::FoiAttachment;
+use crate::error::{AppError, UserError};
+
+pub type UploadJobId = String;
+
+// Treat uploads as expired this long before the server removes them
+const UPLOAD_EXPIRY_MARGIN_MINUTES: i64 = 5;
+
+/// The steps a scanned document goes through until it is attached to its message.
+/// Every stage is persisted, so the pipeline can be resumed from any of them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum UploadStage {
+    /// The document was scanned and is stored locally.
+    Scanned,
+    /// An upload was created on the server, but no data was sent yet.
+    UploadCreated { upload_url: String },
+    /// The upload is in progress, `offset` bytes were confirmed by the server.
+    Uploading { upload_url: String, offset: usize },
+    /// Large files are sent as partial uploads in parallel, which are
+    /// concatenated into a single upload once all of them are complete.
+    UploadingParts { parts: Vec<PartialUpload> },
+    /// All data was sent to the server.
+    Uploaded { upload_url: String },
+    /// The upload was attached to the message, only local cleanup is left.
+    Attached { attachment: FoiAttachment },
+}
+
+impl UploadStage {
+    /// The URLs of the uploads on the server that were created and not attached yet.
+    pub fn upload_urls(&self) -> Vec<&str> {
+        match self {
+            UploadStage::UploadCreated { upload_url }
+            | UploadStage::Uploading { upload_url, .. }
+            | UploadStage::Uploaded { upload_url } => vec![upload_url],
+            UploadStage::UploadingParts { parts } => {
+                parts.iter().map(|part| part.upload_url.as_str()).collect()
+            }
+            UploadStage::Scanned | UploadStage::Attached { .. } => vec![],
+        }
+    }
+}
+
+/// A part of a file sent as its own partial upload.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PartialUpload {
+    pub upload_url: String,
+    /// The `Location` as the server returned it, used to reference the part when concatenating
+    pub location: String,
+    /// Position of the part in the file
+    pub start: usize,
+    pub len: usize,
+    /// Bytes of the part confirmed by the server
+    pub offset: usize,
+}
+
+/// A scanned document waiting to be uploaded and attached to a message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UploadJob {
+    pub id: UploadJobId,
+    pub file_path: String,
+    pub message_resource_uri: String,
+    pub stage: UploadStage,
+    /// Error of the last failed attempt to advance the stage.
+    pub error: Option<String>,
+    pub created_at: String,
+    /// When the server will remove the unfinished upload (RFC 3339).
+    #[serde(default)]
+    pub upload_expires_at: Option<String>,
+}
+
+impl UploadJob {
+    pub fn new(id: UploadJobId, file_path: String, message_resource_uri: String) -> Self {
+        UploadJob {
+            id,
+            file_path,
+            message_resource_uri,
+            stage: UploadStage::Scanned,
+            error: None,
+            created_at: Local::now().to_rfc3339(),
+            upload_expires_at: None,
+        }
+    }
+
+    /// Whether the unfinished upload on the server has expired
+    /// or is about to expire, so it has to be created again.
+    pub fn upload_expired(&self) -> bool {
+        let expires_at = match self
+            .upload_expires_at
+            .as_deref()
+            .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+        {
+            Some(expires_at) => expires_at,
+            None => return false,
+        };
+        expires_at < Utc::now() + TimeDelta::minutes(UPLOAD_EXPIRY_MARGIN_MINUTES)
+    }
+
+    /// Remove the scanned file of this job from disk if it still exists.
+    pub fn remove_file(&self) -> Result<(), AppError> {
+        let file_path = Path::new(&self.file_path);
+        if file_path.exists() {
+            std::fs::remove_file(file_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Generate a new job id that can also be used as part of a file name.
+pub fn new_job_id() -> UploadJobId {
+    Local::now().format("%Y%m%d%H%M%S%3f").to_string()
+}
+
+pub fn find_job(state: &State<'_, Mutex<AppState>>, job_id: &str) -> Option<UploadJob> {
+    let state = state.lock().unwrap();
+    state
+        .upload_queue
+        .iter()
+        .find(|job| job.id == job_id)
+        .cloned()
+}
+
+pub fn get_job(state: &State<'_, Mutex<AppState>>, job_id: &str) -> Result<UploadJob, AppError> {
+    find_job(state, job_id)
+        .ok_or_else(|| UserError(format!("Upload job {job_id} not found")).into())
+}
+
+/// Apply `update` to the job with the given id and persist the queue.
+/// The queue is saved after every change so the pipeline survives the app being killed.
+pub fn update_job<F>(
+    app_handle: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    job_id: &str,
+    update: F,
+) -> Result<(), AppError>
+where
+    F: FnOnce(&mut UploadJob),
+{
+    let mut state = state.lock().unwrap();
+    if let Some(job) = state.upload_queue.iter_mut().find(|job| job.id == job_id) {
+        update(job);
+    }
+    state.save(app_handle)?;
+    Ok(())
+}
+
+pub fn remove_job(
+    app_handle: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    job_id: &str,
+) -> Result<Option<UploadJob>, AppError> {
+    let mut state = state.lock().unwrap();
+    let job = state
+        .upload_queue
+        .iter()
+        .position(|job| job.id == job_id)
+        .map(|index| state.upload_queue.remove(index));
+    state.save(app_handle)?;
+    Ok(job)
+}
+
+/// Remove all jobs uploading to the given message, e.g. because it was deleted.
+pub fn remove_message_jobs(
+    app_handle: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    message_resource_uri: &str,
+) -> Result<Vec<UploadJob>, AppError> {
+    let mut state = state.lock().unwrap();
+    let (removed, kept) = std::mem::take(&mut state.upload_queue)
+        .into_iter()
+        .partition(|job| job.message_resource_uri == message_resource_uri);
+    state.upload_queue = kept;
+    state.save(app_handle)?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_expiring_at(expires_at: Option<DateTime<Utc>>) -> UploadJob {
+        let mut job = UploadJob::new(
+            new_job_id(),
+            "scan.pdf".to_string(),
+            "/api/v1/message/1/".to_string(),
+        );
+        job.upload_expires_at = expires_at.map(|expires_at| expires_at.to_rfc3339());
+        job
+    }
+
+    #[test]
+    fn upload_expired() {
+        assert!(job_expiring_at(Some(Utc::now() - TimeDelta::hours(1))).upload_expired());
+        // Too close to the expiry to finish the upload
+        assert!(job_expiring_at(Some(Utc::now() + TimeDelta::minutes(1))).upload_expired());
+        assert!(!job_expiring_at(Some(Utc::now() + TimeDelta::hours(1))).upload_expired());
+        // Uploads without expiry are kept by the server
+        assert!(!job_expiring_at(None).upload_expired());
+    }
+
+    #[test]
+    fn invalid_expiry_is_ignored() {
+        let mut job = job_expiring_at(None);
+        job.upload_expires_at = Some("gestern".to_string());
+        assert!(!job.upload_expired());
+    }
+}
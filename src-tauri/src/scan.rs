@@ -1,40 +1,52 @@
-use std::path::PathBuf;
+use futures_util::future::try_join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use tauri::{Emitter, Manager, State};
 use tauri_plugin_documentcamera::{DocumentCameraExt, ScanRequest};
 
 use crate::AppState;
 use crate::account::ensure_valid_token;
-use crate::api::{FoiAttachment, create_attachment, create_upload, get_tus_client, resume_upload};
-use crate::error::AppError;
+use crate::api::{FoiAttachment, get_froide_client};
+use crate::client::FroideClient;
+use crate::error::{AppError, TusError, UserError};
+use crate::queue::{
+    UploadJob, UploadJobId, UploadStage, find_job, get_job, new_job_id, remove_job, update_job,
+};
+use crate::tus::{TusClient, UploadProgress, earliest_expiry};
 
-fn reset_upload_state(
-    app_handle: &tauri::AppHandle,
-    state: &State<'_, Mutex<AppState>>,
-) -> Result<(), AppError> {
-    let mut state = state.lock().unwrap();
-    state.upload_url = None;
-    state.file_path = None;
-    state.message_resource_uri = None;
-    state.save(app_handle)?;
-    Ok(())
+/// Payload of the `upload-progress` event emitted after every uploaded chunk.
+#[derive(Clone, Serialize)]
+struct UploadProgressEvent {
+    job_id: UploadJobId,
+    bytes_uploaded: usize,
+    total_size: usize,
+    /// Until when the upload can be resumed (RFC 3339).
+    expires_at: Option<String>,
 }
 
+/// Scan a document and queue it for upload to the message.
+/// Returns the id of the upload job or `None` if the scan was cancelled.
 #[tauri::command(rename_all = "snake_case")]
 pub async fn scan_document(
     app_handle: tauri::AppHandle,
     state: State<'_, Mutex<AppState>>,
     message_resource_uri: String,
-) -> Result<bool, AppError> {
+) -> Result<Option<UploadJobId>, AppError> {
     log::info!("scan document in main called");
 
-    let file_path = app_handle.path().app_local_data_dir()?.join("scan.pdf");
+    let job_id = new_job_id();
+    let file_path = app_handle
+        .path()
+        .app_local_data_dir()?
+        .join(format!("scan_{job_id}.pdf"));
     let result = app_handle.documentcamera().scan(ScanRequest {
         path: file_path.to_str().unwrap().to_string(),
     })?;
     let file_path = match result.path {
         Some(path) => path,
-        None => return Ok(false),
+        None => return Ok(None),
     };
     let file_path = PathBuf::from(file_path);
 
@@ -52,73 +64,447 @@ pub async fn scan_document(
     }
     {
         let mut state = state.lock().unwrap();
-        state.message_resource_uri = Some(message_resource_uri);
-        state.file_path = Some(file_path.to_str().unwrap().to_string());
+        state.upload_queue.push(UploadJob::new(
+            job_id.clone(),
+            file_path.to_str().unwrap().to_string(),
+            message_resource_uri,
+        ));
         state.save(&app_handle)?;
     }
-    Ok(true)
+    Ok(Some(job_id))
+}
+
+fn emit_upload_progress(app: &tauri::AppHandle, job_id: &str, progress: UploadProgress) {
+    let event = UploadProgressEvent {
+        job_id: job_id.to_string(),
+        bytes_uploaded: progress.bytes_uploaded,
+        total_size: progress.total_size,
+        expires_at: progress.expires_at.map(|e| e.to_rfc3339()),
+    };
+    if let Err(err) = app.emit("upload-progress", event) {
+        log::warn!("Could not report upload progress: {err}");
+    }
+}
+
+/// Perform the next step of the upload pipeline for the given job
+/// and return the stage it reached.
+async fn advance_job(
+    app: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    client: &FroideClient,
+    tus_client: &TusClient,
+    job: &UploadJob,
+) -> Result<UploadStage, AppError> {
+    let file_path = Path::new(&job.file_path);
+    let stage = match &job.stage {
+        UploadStage::Scanned if client.use_parallel_upload(tus_client, file_path)? => {
+            let (parts, expires_at) = client.create_partial_uploads(tus_client, file_path).await?;
+            update_job(app, state, &job.id, |job| {
+                job.upload_expires_at = expires_at.map(|e| e.to_rfc3339());
+            })?;
+            app.emit("scan-progress", "upload_created")?;
+            UploadStage::UploadingParts { parts }
+        }
+        UploadStage::Scanned => {
+            let upload = client.create_upload(tus_client, file_path).await?;
+            update_job(app, state, &job.id, |job| {
+                job.upload_expires_at = upload.expires_at.map(|e| e.to_rfc3339());
+            })?;
+            app.emit("scan-progress", "upload_created")?;
+
+            let file_len = file_path.metadata()?.len() as usize;
+            if upload.bytes_uploaded > 0 {
+                let progress = UploadProgress {
+                    bytes_uploaded: upload.bytes_uploaded,
+                    total_size: file_len,
+                    expires_at: upload.expires_at,
+                };
+                emit_upload_progress(app, &job.id, progress);
+            }
+            if upload.bytes_uploaded >= file_len {
+                // The whole file was sent with the creation request
+                app.emit("scan-progress", "upload_complete")?;
+                UploadStage::Uploaded {
+                    upload_url: upload.location,
+                }
+            } else if upload.bytes_uploaded > 0 {
+                UploadStage::Uploading {
+                    upload_url: upload.location,
+                    offset: upload.bytes_uploaded,
+                }
+            } else {
+                UploadStage::UploadCreated {
+                    upload_url: upload.location,
+                }
+            }
+        }
+        UploadStage::UploadCreated { upload_url } | UploadStage::Uploading { upload_url, .. } => {
+            if job.upload_expired() {
+                log::warn!("upload_document: upload at {upload_url:?} has expired");
+                return Ok(UploadStage::Scanned);
+            }
+            // Continue at the persisted offset, a wrong one is corrected by the server
+            let offset = match job.stage {
+                UploadStage::Uploading { offset, .. } => Some(offset),
+                _ => None,
+            };
+            let on_progress = |progress: UploadProgress| {
+                // Persist the offset so the stage reflects what the server confirmed
+                let result = update_job(app, state, &job.id, |job| {
+                    job.stage = UploadStage::Uploading {
+                        upload_url: upload_url.clone(),
+                        offset: progress.bytes_uploaded,
+                    };
+                    // Without asking the server the expiry is only known after the first chunk
+                    if let Some(expires_at) = progress.expires_at {
+                        job.upload_expires_at = Some(expires_at.to_rfc3339());
+                    }
+                });
+                if let Err(err) = result {
+                    log::warn!("Could not persist upload progress: {err}");
+                }
+                emit_upload_progress(app, &job.id, progress);
+            };
+            if !client
+                .resume_upload(tus_client, upload_url, offset, file_path, on_progress)
+                .await?
+            {
+                log::warn!("upload_document: upload does not exist at {upload_url:?}");
+                return Ok(UploadStage::Scanned);
+            }
+            app.emit("scan-progress", "upload_complete")?;
+            UploadStage::Uploaded {
+                upload_url: upload_url.clone(),
+            }
+        }
+        UploadStage::UploadingParts { parts } => {
+            let upload_urls: Vec<_> = parts.iter().map(|part| part.upload_url.as_str()).collect();
+            if job.upload_expired() {
+                log::warn!("upload_document: partial uploads have expired");
+                client.discard_uploads(tus_client, &upload_urls).await;
+                return Ok(UploadStage::Scanned);
+            }
+            let total_size = file_path.metadata()?.len() as usize;
+            let part_offsets = Mutex::new(parts.iter().map(|part| part.offset).collect::<Vec<_>>());
+            let part_expiries = Mutex::new(vec![None; parts.len()]);
+            let on_progress = |index: usize, progress: UploadProgress| {
+                let bytes_uploaded = {
+                    let mut part_offsets = part_offsets.lock().unwrap();
+                    part_offsets[index] = progress.bytes_uploaded;
+                    part_offsets.iter().sum()
+                };
+                // The job expires with the first of its parts
+                let expires_at = {
+                    let mut part_expiries = part_expiries.lock().unwrap();
+                    part_expiries[index] = progress.expires_at;
+                    part_expiries.iter().copied().fold(None, earliest_expiry)
+                };
+                // Persist the offset of the part so each part resumes where it stopped
+                let result = update_job(app, state, &job.id, |job| {
+                    if let UploadStage::UploadingParts { parts } = &mut job.stage {
+                        parts[index].offset = progress.bytes_uploaded;
+                    }
+                    job.upload_expires_at = expires_at.map(|e| e.to_rfc3339());
+                });
+                if let Err(err) = result {
+                    log::warn!("Could not persist upload progress: {err}");
+                }
+                let progress = UploadProgress {
+                    bytes_uploaded,
+                    total_size,
+                    expires_at,
+                };
+                emit_upload_progress(app, &job.id, progress);
+            };
+            let found = try_join_all(parts.iter().enumerate().map(|(index, part)| {
+                let on_progress = &on_progress;
+                client.resume_partial_upload(tus_client, part, file_path, move |progress| {
+                    on_progress(index, progress)
+                })
+            }))
+            .await?;
+            if found.contains(&false) {
+                log::warn!("upload_document: a partial upload does not exist anymore");
+                client.discard_uploads(tus_client, &upload_urls).await;
+                return Ok(UploadStage::Scanned);
+            }
+            let upload = client.concatenate_uploads(tus_client, parts).await?;
+            app.emit("scan-progress", "upload_complete")?;
+            UploadStage::Uploaded {
+                upload_url: upload.location,
+            }
+        }
+        UploadStage::Uploaded { upload_url } => {
+            let attachment = client
+                .create_attachment(&job.message_resource_uri, upload_url)
+                .await?;
+            app.emit("scan-progress", "attachment_created")?;
+            UploadStage::Attached { attachment }
+        }
+        UploadStage::Attached { .. } => job.stage.clone(),
+    };
+    Ok(stage)
+}
+
+/// Run the upload pipeline of a single job from its persisted stage
+/// until the document is attached to its message.
+/// Returns `None` if the scanned file is gone and the job was dropped
+/// or if the job was cancelled in the meantime.
+async fn upload_job(
+    app: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    job_id: &str,
+) -> Result<Option<FoiAttachment>, AppError> {
+    let client = get_froide_client(app, state);
+    let tus_client = client.tus_client().await?;
+    let mut restarts = 0;
+
+    loop {
+        let Some(job) = find_job(state, job_id) else {
+            log::info!("upload_document: upload job {job_id} was cancelled");
+            return Ok(None);
+        };
+        let needs_file = matches!(
+            job.stage,
+            UploadStage::Scanned
+                | UploadStage::UploadCreated { .. }
+                | UploadStage::Uploading { .. }
+                | UploadStage::UploadingParts { .. }
+        );
+
+        if let UploadStage::Attached { ref attachment } = job.stage {
+            // Only remove the scan once it is safely attached
+            job.remove_file()?;
+            remove_job(app, state, job_id)?;
+            return Ok(Some(attachment.clone()));
+        }
+
+        if needs_file && !Path::new(&job.file_path).exists() {
+            remove_job(app, state, job_id)?;
+            log::warn!(
+                "upload_document: file does not exist at {:?}",
+                job.file_path
+            );
+            return Ok(None);
+        }
+
+        let stage = advance_job(app, state, &client, &tus_client, &job).await?;
+        if find_job(state, job_id).is_none() {
+            // Cancelled while the step was running, don't leave an upload behind
+            client
+                .discard_uploads(&tus_client, &stage.upload_urls())
+                .await;
+            continue;
+        }
+        if matches!(stage, UploadStage::Scanned) {
+            // Upload expired or vanished on the server, start over but only once
+            restarts += 1;
+            if restarts > 1 {
+                return Err(TusError::NotFoundError.into());
+            }
+        }
+        update_job(app, state, job_id, |job| {
+            if matches!(stage, UploadStage::Scanned) {
+                job.upload_expires_at = None;
+            }
+            job.stage = stage;
+            job.error = None;
+        })?;
+    }
+}
+
+/// Only one job is uploaded at a time, running a job twice would upload its scan twice.
+fn check_no_active_upload(state: &AppState) -> Result<(), AppError> {
+    match state.active_upload_job {
+        Some(ref job_id) => {
+            Err(UserError(format!("Upload job {job_id} is already running")).into())
+        }
+        None => Ok(()),
+    }
+}
+
+async fn run_upload_job(
+    app: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    job_id: &str,
+) -> Result<Option<FoiAttachment>, AppError> {
+    {
+        let mut state = state.lock().unwrap();
+        check_no_active_upload(&state)?;
+        state.active_upload_job = Some(job_id.to_string());
+    }
+    let result = upload_job(app, state, job_id).await;
+    {
+        let mut state = state.lock().unwrap();
+        if state.active_upload_job.as_deref() == Some(job_id) {
+            state.active_upload_job = None;
+        }
+    }
+    match result {
+        Ok(att) => Ok(att),
+        // Failures caused by cancelling the job are expected
+        Err(_) if find_job(state, job_id).is_none() => Ok(None),
+        Err(err) => {
+            log::error!("upload job {job_id} failed: {err}");
+            update_job(app, state, job_id, |job| {
+                job.error = Some(err.to_string());
+            })?;
+            Err(err)
+        }
+    }
+}
+
+/// Outcome of working through the upload queue.
+#[derive(Serialize)]
+pub struct UploadResult {
+    /// Attachments of the documents that were uploaded by their job.
+    attachments: HashMap<UploadJobId, FoiAttachment>,
+    /// Jobs that failed, their error is recorded on the job.
+    failed_jobs: Vec<UploadJobId>,
 }
 
+/// Upload all queued documents in order.
+/// A failed job stays queued with its error and does not block the jobs after it.
 #[tauri::command]
 pub async fn upload_document(
     app: tauri::AppHandle,
     state: State<'_, Mutex<AppState>>,
-) -> Result<Option<FoiAttachment>, AppError> {
+) -> Result<UploadResult, AppError> {
     log::info!("upload document document in main called");
-    let (message_resource_uri, file_path, upload_url) = {
+    let job_ids: Vec<UploadJobId> = {
         let state = state.lock().unwrap();
-        (
-            state.message_resource_uri.clone(),
-            state.file_path.clone(),
-            state.upload_url.clone(),
-        )
+        check_no_active_upload(&state)?;
+        state
+            .upload_queue
+            .iter()
+            .map(|job| job.id.clone())
+            .collect()
+    };
+    let mut result = UploadResult {
+        attachments: HashMap::new(),
+        failed_jobs: vec![],
     };
 
-    let (message_resource_uri, file_path) = match (message_resource_uri, file_path) {
-        (Some(message_resource_uri), Some(file_path)) => (message_resource_uri, file_path),
-        _ => {
-            log::warn!("upload_document: missing message_resource_uri or file_path");
-            return Ok(None);
+    if job_ids.is_empty() {
+        log::info!("upload_document: upload queue is empty");
+        return Ok(result);
+    }
+
+    if let Err(err) = ensure_valid_token(&app, &state).await {
+        if !err.is_connection_error() {
+            return Err(err);
         }
-    };
+        // Offline, none of the jobs can be uploaded
+        log::warn!("upload_document: could not refresh token: {err}");
+        for job_id in &job_ids {
+            update_job(&app, &state, job_id, |job| {
+                job.error = Some(err.to_string());
+            })?;
+        }
+        result.failed_jobs = job_ids;
+        return Ok(result);
+    }
 
-    let file_path = PathBuf::from(file_path);
-    if !file_path.exists() {
-        reset_upload_state(&app, &state)?;
-        log::warn!("upload_document: file does not exist at {file_path:?}");
-        return Ok(None);
+    for job_id in job_ids {
+        match run_upload_job(&app, &state, &job_id).await {
+            Ok(Some(attachment)) => {
+                result.attachments.insert(job_id, attachment);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                result.failed_jobs.push(job_id);
+                if err.is_connection_error() {
+                    // The remaining jobs would fail the same way
+                    break;
+                }
+            }
+        }
     }
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn list_upload_jobs(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<Vec<UploadJob>, AppError> {
+    let state = state.lock().unwrap();
+    Ok(state.upload_queue.clone())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub async fn retry_upload_job(
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    job_id: UploadJobId,
+) -> Result<Option<FoiAttachment>, AppError> {
+    get_job(&state, &job_id)?;
+    update_job(&app, &state, &job_id, |job| {
+        job.error = None;
+    })?;
 
     ensure_valid_token(&app, &state).await?;
 
-    let tus_client = get_tus_client(&state)?;
+    run_upload_job(&app, &state, &job_id).await
+}
 
-    let upload_url = match upload_url {
-        Some(upload_url) => upload_url,
-        None => {
-            let upload_url = create_upload(&tus_client, &file_path).await?;
-            {
-                let mut state = state.lock().unwrap();
-                state.upload_url = Some(upload_url.clone());
-                state.save(&app)?;
-            }
-            app.emit("scan-progress", "upload_created")?;
-            upload_url
+/// Remove a job and its scanned file without contacting the server.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn discard_upload_job(
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    job_id: UploadJobId,
+) -> Result<bool, AppError> {
+    match remove_job(&app, &state, &job_id)? {
+        Some(job) => {
+            job.remove_file()?;
+            Ok(true)
         }
-    };
+        None => Ok(false),
+    }
+}
 
-    let result = resume_upload(&tus_client, &upload_url, &file_path).await?;
-    if !result {
-        reset_upload_state(&app, &state)?;
-        log::warn!("upload_document: upload does not exist at {upload_url:?}");
-        return Ok(None);
+/// Delete the uploads of a removed job on the server.
+/// Failures of single uploads are only logged, the server removes them once they expire.
+pub async fn discard_job_uploads(
+    app: &tauri::AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    job: &UploadJob,
+) -> Result<(), AppError> {
+    let upload_urls = job.stage.upload_urls();
+    if upload_urls.is_empty() {
+        return Ok(());
     }
-    app.emit("scan-progress", "upload_complete")?;
-    std::fs::remove_file(&file_path)?;
+    ensure_valid_token(app, state).await?;
+    let client = get_froide_client(app, state);
+    let tus_client = client.tus_client().await?;
+    client.discard_uploads(&tus_client, &upload_urls).await;
+    Ok(())
+}
+
+/// Cancel the given job or the job currently being uploaded if no id is given.
+/// The scanned file is deleted and the upload is removed from the server if it can be reached.
+#[tauri::command(rename_all = "snake_case")]
+pub async fn cancel_upload(
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    job_id: Option<UploadJobId>,
+) -> Result<bool, AppError> {
+    let job_id = match job_id.or_else(|| state.lock().unwrap().active_upload_job.clone()) {
+        Some(job_id) => job_id,
+        None => return Ok(false),
+    };
 
-    let att = create_attachment(&state, message_resource_uri, &upload_url).await?;
-    app.emit("scan-progress", "attachment_created")?;
-    reset_upload_state(&app, &state)?;
+    let job = match remove_job(&app, &state, &job_id)? {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+    job.remove_file()?;
 
-    Ok(Some(att))
+    // The job is gone locally, an unreachable server must not fail the cancel
+    if let Err(err) = discard_job_uploads(&app, &state, &job).await {
+        log::warn!("Could not remove uploads of cancelled job {job_id}: {err}");
+    }
+    app.emit("scan-progress", "upload_cancelled")?;
+
+    Ok(true)
 }
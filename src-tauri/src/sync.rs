@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use chrono::TimeDelta;
+use chrono::prelude::*;
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use tauri::{AppHandle, Emitter, State};
+
+use crate::AppState;
+use crate::api::{
+    FoiMessage, FoiRequest, FoiRequestId, MessageKind, get_froide_client, get_user_id,
+};
+use crate::cache::ApiCache;
+use crate::client::FroideClient;
+use crate::error::{AppError, UserError};
+
+const REQUESTS_CACHE_KEY: &str = "requests";
+// Start of the last successful sync, requests changed after it are fetched next time
+const REQUESTS_SYNCED_AT_KEY: &str = "requests_synced_at";
+// Start of the last full sync, which also picks up changes without new messages
+const REQUESTS_FULL_SYNCED_AT_KEY: &str = "requests_full_synced_at";
+// Incremental syncs only see requests with new messages, e.g. not a changed status
+// or deleted requests, so all requests are fetched at least this often
+const FULL_SYNC_INTERVAL_HOURS: i64 = 24;
+// How many requests have their messages fetched at the same time
+const MESSAGE_SYNC_CONCURRENCY: usize = 4;
+
+/// Whether the last full sync is too long ago.
+fn full_sync_due(cache: &ApiCache) -> bool {
+    match cache
+        .get::<String>(REQUESTS_FULL_SYNCED_AT_KEY)
+        .and_then(|synced_at| synced_at.parse::<DateTime<Utc>>().ok())
+    {
+        Some(synced_at) => Utc::now() - synced_at > TimeDelta::hours(FULL_SYNC_INTERVAL_HOURS),
+        None => true,
+    }
+}
+
+/// Bring the cached requests of the user up to date and emit them as `foirequest-list`,
+/// the cached requests first and then the fetched ones page by page.
+/// Only requests with messages since the last sync are fetched, unless `full` is set,
+/// nothing was synced yet or the last full sync is too long ago. A full sync removes
+/// requests that do not exist anymore and emits their ids as `foirequest-removed`.
+/// Cached messages of requests with new messages are fetched again.
+/// Returns `false` if the server could not be reached and only cached requests were emitted.
+pub async fn sync_requests(
+    app: &AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    full: bool,
+) -> Result<bool, AppError> {
+    let user_id = get_user_id(state)?;
+    let client = get_froide_client(app, state);
+    let cache =
+        ApiCache::open(app, state)?.ok_or_else(|| UserError("No user found".to_string()))?;
+
+    let cached_requests = cache.get::<Vec<FoiRequest>>(REQUESTS_CACHE_KEY);
+    if let Some(ref requests) = cached_requests {
+        app.emit("foirequest-list", requests)?;
+    }
+    let synced_at = match cached_requests {
+        Some(_) if !full && !full_sync_due(&cache) => cache.get::<String>(REQUESTS_SYNCED_AT_KEY),
+        _ => None,
+    };
+    // Taken before fetching, so changes during the sync are fetched again next time
+    let sync_started_at = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+
+    let mut next = Some(match synced_at {
+        Some(ref since) => client.changed_requests_url(user_id, since),
+        None => client.requests_url(user_id),
+    });
+    let mut fetched = vec![];
+    while let Some(next_url) = next {
+        let api_response = match client.get_page::<FoiRequest>(&next_url).await {
+            Ok(api_response) => api_response,
+            Err(err) if cached_requests.is_some() && err.is_connection_error() => {
+                log::warn!("Showing cached requests: {err}");
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        };
+        // Emit early to show progress
+        app.emit("foirequest-list", &api_response.objects)?;
+
+        fetched.extend(api_response.objects);
+        next = api_response.meta.next;
+    }
+
+    let cached_requests = cached_requests.unwrap_or_default();
+    // Compared to the cached requests, so a server ignoring the filter costs no extra requests
+    let last_messages: HashMap<_, _> = cached_requests
+        .iter()
+        .map(|request| (request.id, request.last_message.as_str()))
+        .collect();
+    let with_new_messages: Vec<_> = fetched
+        .iter()
+        .filter(|request| {
+            last_messages
+                .get(&request.id)
+                .is_some_and(|last_message| *last_message != request.last_message)
+        })
+        .map(|request| request.id)
+        .collect();
+    sync_cached_messages(app, &client, &cache, &with_new_messages).await;
+
+    let fetched_ids: HashSet<_> = fetched.iter().map(|request| request.id).collect();
+    let requests = match synced_at {
+        Some(_) => {
+            log::info!("Synced {} changed requests", fetched.len());
+            let mut requests = fetched;
+            requests.extend(
+                cached_requests
+                    .into_iter()
+                    .filter(|request| !fetched_ids.contains(&request.id)),
+            );
+            requests
+        }
+        None => {
+            let removed: Vec<_> = cached_requests
+                .iter()
+                .map(|request| request.id)
+                .filter(|id| !fetched_ids.contains(id))
+                .collect();
+            if !removed.is_empty() {
+                log::info!("Removing {} deleted requests", removed.len());
+                for id in &removed {
+                    cache.delete(&format!("request/{id}"));
+                    cache.delete(&format!("messages/{id}"));
+                }
+                app.emit("foirequest-removed", &removed)?;
+            }
+            cache.set(REQUESTS_FULL_SYNCED_AT_KEY, &sync_started_at);
+            fetched
+        }
+    };
+
+    cache.set(REQUESTS_CACHE_KEY, &requests);
+    cache.set(REQUESTS_SYNCED_AT_KEY, &sync_started_at);
+    Ok(true)
+}
+
+/// Fetch the cached messages of the given requests again, a few requests at a time.
+/// Messages of requests that were never opened are fetched when needed.
+async fn sync_cached_messages(
+    app: &AppHandle,
+    client: &FroideClient,
+    cache: &ApiCache,
+    request_ids: &[FoiRequestId],
+) {
+    let cached: Vec<_> = request_ids
+        .iter()
+        .copied()
+        .filter(|id| {
+            cache
+                .get::<Vec<FoiMessage>>(&format!("messages/{id}"))
+                .is_some()
+        })
+        .collect();
+    stream::iter(cached)
+        .map(|id| async move {
+            let messages = client.get_messages(id, &MessageKind::APP_KINDS).await;
+            (id, messages)
+        })
+        .buffer_unordered(MESSAGE_SYNC_CONCURRENCY)
+        .for_each(|(id, result)| {
+            match result {
+                Ok(messages) => {
+                    cache.update(app, &format!("messages/{id}"), "foimessage-list", messages)
+                }
+                // Still revalidated when the request is opened
+                Err(err) => log::warn!("Could not sync messages of {id}: {err}"),
+            }
+            future::ready(())
+        })
+        .await;
+}
+
+/// Replace a changed request in the cached list and its own cache entry.
+pub fn update_cached_request(app: &AppHandle, cache: &ApiCache, request: &FoiRequest) {
+    if let Some(mut requests) = cache.get::<Vec<FoiRequest>>(REQUESTS_CACHE_KEY)
+        && let Some(cached) = requests.iter_mut().find(|cached| cached.id == request.id)
+    {
+        *cached = request.clone();
+        cache.set(REQUESTS_CACHE_KEY, &requests);
+    }
+    let key = format!("request/{}", request.id);
+    cache.update(app, &key, "foirequest-updated", request.clone());
+}
+
+/// Replace a changed message in the cached messages of its request and its own cache entry.
+pub fn update_cached_message(app: &AppHandle, cache: &ApiCache, message: &FoiMessage) {
+    if let Some(request_id) = message.request_id() {
+        let key = format!("messages/{request_id}");
+        if let Some(mut messages) = cache.get::<Vec<FoiMessage>>(&key)
+            && let Some(cached) = messages.iter_mut().find(|cached| cached.id == message.id)
+        {
+            *cached = message.clone();
+            cache.update(app, &key, "foimessage-list", messages);
+        }
+    }
+    let key = format!("message/{}", message.id);
+    cache.update(app, &key, "foimessage-updated", message.clone());
+}
+
+/// Remove a deleted message from the cached messages of its request.
+pub fn remove_cached_message(app: &AppHandle, cache: &ApiCache, message: &FoiMessage) {
+    if let Some(request_id) = message.request_id() {
+        let key = format!("messages/{request_id}");
+        if let Some(mut messages) = cache.get::<Vec<FoiMessage>>(&key) {
+            messages.retain(|cached| cached.id != message.id);
+            cache.update(app, &key, "foimessage-list", messages);
+        }
+    }
+    cache.delete(&format!("message/{}", message.id));
+}
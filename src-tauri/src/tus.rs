@@ -44,11 +44,22 @@
 
 // use crate::http::{default_headers, Headers, HttpMethod, HttpRequest};
 use crate::error::TusError;
+use bytes::Bytes;
+use chrono::{DateTime, FixedOffset};
+use md5::Md5;
+use reqwest::header::HeaderMap;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::ops::Range;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 /// Indicates a byte offset withing a resource.
 pub const UPLOAD_OFFSET: &str = "upload-offset";
@@ -68,24 +79,134 @@ pub const UPLOAD_METADATA: &str = "upload-metadata";
 /// Use this header if its environment does not support the PATCH or DELETE methods.
 pub const LOCATION: &str = "location";
 
+/// Marks an upload as partial or as the concatenation of partial uploads.
+pub const UPLOAD_CONCAT: &str = "upload-concat";
+
+/// When an unfinished upload will be removed by the server.
+pub const UPLOAD_EXPIRES: &str = "upload-expires";
+
+/// A list of the protocol versions supported by the server.
+pub const TUS_VERSION: &str = "tus-version";
+
+/// The maximum allowed size of an entire upload in bytes.
+pub const TUS_MAX_SIZE: &str = "tus-max-size";
+
+/// A list of the extensions supported by the server.
+pub const TUS_EXTENSION: &str = "tus-extension";
+
+/// A list of the checksum algorithms supported by the server.
+pub const TUS_CHECKSUM_ALGORITHM: &str = "tus-checksum-algorithm";
+
+/// The checksum algorithm and the base64 encoded checksum of the request body.
+pub const UPLOAD_CHECKSUM: &str = "upload-checksum";
+
+/// The protocol version spoken by this client.
+const PROTOCOL_VERSION: &str = "1.0.0";
+
+/// Size of the first chunk, and of all chunks unless the client is given chunk size limits.
 const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
 
+/// How long sending a single chunk should take at the measured throughput.
+const TARGET_CHUNK_DURATION: Duration = Duration::from_secs(5);
+
+/// How often a chunk is sent again after the server reported a checksum mismatch.
+const MAX_CHECKSUM_RETRIES: usize = 3;
+
+/// How often a request is retried after a transient failure or an offset mismatch.
+const MAX_RETRIES: u32 = 5;
+
+/// Delay before the first retry, doubled for every further retry.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound for the delay between retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Time a chunk is given to upload at least, regardless of its size.
+const MIN_CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Slowest throughput in bytes per second a chunk is given time for before it times out.
+const MIN_CHUNK_THROUGHPUT: u64 = 16 * 1024;
+
+/// Timeout for sending a chunk of `len` bytes, so a stalled request fails and is retried.
+fn chunk_timeout(len: usize) -> Duration {
+    MIN_CHUNK_TIMEOUT + Duration::from_secs(len as u64 / MIN_CHUNK_THROUGHPUT)
+}
+
+/// Exponential backoff delay before the given retry.
+fn retry_delay(retry: u32) -> Duration {
+    INITIAL_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
 /// Used to interact with a [tus](https://tus.io) endpoint.
 pub struct TusClient {
     client: reqwest::Client,
+    headers: RwLock<HeaderMap>,
+    server_info: ServerInfo,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    min_chunk_size: usize,
+    max_chunk_size: usize,
 }
 
 impl TusClient {
     /// Instantiates a new instance of `Client`. `http_handler` needs to implement the `HttpHandler` trait.
     /// A default implementation of this trait for the `reqwest` library is available by enabling the `reqwest` feature.
     pub fn new(client: reqwest::Client) -> Self {
-        TusClient { client }
+        TusClient {
+            client,
+            headers: RwLock::new(HeaderMap::new()),
+            server_info: ServerInfo::default(),
+            checksum_algorithm: None,
+            min_chunk_size: DEFAULT_CHUNK_SIZE,
+            max_chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Replace the headers sent with every request, e.g. for authorization.
+    pub fn set_headers(&self, headers: HeaderMap) {
+        *self.headers.write().unwrap() = headers;
+    }
+
+    /// Limit the range in which the chunk size is adapted to the measured throughput.
+    /// Without limits or with the same value for both, chunks have a fixed size.
+    pub fn with_chunk_size_limits(mut self, min_chunk_size: usize, max_chunk_size: usize) -> Self {
+        self.min_chunk_size = min_chunk_size.max(1);
+        self.max_chunk_size = max_chunk_size.max(self.min_chunk_size);
+        self
+    }
+
+    /// Whether the server supports the given extension.
+    pub fn supports(&self, extension: TusExtension) -> bool {
+        self.server_info.supports(extension)
+    }
+
+    /// Only use the optional extensions the server supports according to `server_info`.
+    pub fn with_server_info(mut self, server_info: ServerInfo) -> Self {
+        self.checksum_algorithm = server_info.checksum_algorithm();
+        self.server_info = server_info;
+        self
     }
 
     fn create_request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
         self.client
             .request(method, url)
-            .header(TUS_RESUMABLE, "1.0.0")
+            .headers(self.headers.read().unwrap().clone())
+            .header(TUS_RESUMABLE, PROTOCOL_VERSION)
+    }
+
+    /// Get info about the capabilities of the server.
+    pub async fn get_server_info(&self, url: &str) -> Result<ServerInfo, TusError> {
+        let response = self
+            .create_request(reqwest::Method::OPTIONS, url)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(TusError::UnexpectedStatusCode(response.status().as_u16()));
+        }
+
+        ServerInfo::from_headers(response.headers())
     }
 
     /// Get info about a file on the server.
@@ -104,7 +225,13 @@ impl TusClient {
             .get(UPLOAD_LENGTH)
             .and_then(|l| l.to_str().ok()?.parse::<usize>().ok());
 
-        if response.status().is_client_error() || bytes_uploaded.is_none() {
+        match response.status().as_u16() {
+            200..=299 => {}
+            404 | 410 => return Err(TusError::NotFoundError),
+            // E.g. a rejected access token, which the caller can refresh
+            status => return Err(TusError::UnexpectedStatusCode(status)),
+        }
+        if bytes_uploaded.is_none() {
             return Err(TusError::NotFoundError);
         }
 
@@ -113,80 +240,310 @@ impl TusClient {
         Ok(UploadInfo {
             bytes_uploaded,
             total_size,
+            expires_at: parse_expires(response.headers()),
         })
     }
 
     /// Upload a file to the specified upload URL.
-    pub async fn upload(&self, url: &str, path: &Path) -> Result<(), TusError> {
-        self.upload_with_chunk_size(url, path, DEFAULT_CHUNK_SIZE)
+    /// `on_progress` is called after every chunk.
+    pub async fn upload<F>(&self, url: &str, path: &Path, on_progress: F) -> Result<(), TusError>
+    where
+        F: Fn(UploadProgress),
+    {
+        self.upload_with_chunk_size(url, path, DEFAULT_CHUNK_SIZE, on_progress)
             .await
     }
 
-    /// Upload a file to the specified upload URL with the given chunk size.
-    pub async fn upload_with_chunk_size(
+    /// Upload a file to the specified upload URL starting with the given chunk size.
+    /// The chunk size is then adapted to the measured throughput within the configured limits.
+    /// `on_progress` is called after every chunk.
+    pub async fn upload_with_chunk_size<F>(
         &self,
         url: &str,
         path: &Path,
         chunk_size: usize,
-    ) -> Result<(), TusError> {
-        let info = self.get_info(url).await?;
-        let file = File::open(path)?;
-        let file_len = file.metadata()?.len() as usize;
-
-        if let Some(total_size) = info.total_size {
-            if file_len != total_size {
-                return Err(TusError::UnequalSizeError);
+        on_progress: F,
+    ) -> Result<(), TusError>
+    where
+        F: Fn(UploadProgress),
+    {
+        let file_len = tokio::fs::metadata(path).await?.len() as usize;
+        self.upload_range(url, path, 0..file_len, None, chunk_size, on_progress)
+            .await
+    }
+
+    /// Continue an upload at `offset`, e.g. as returned by the creation request,
+    /// without asking the server for the offset first.
+    /// `on_progress` is called after every chunk.
+    pub async fn upload_from<F>(
+        &self,
+        url: &str,
+        path: &Path,
+        offset: usize,
+        on_progress: F,
+    ) -> Result<(), TusError>
+    where
+        F: Fn(UploadProgress),
+    {
+        let file_len = tokio::fs::metadata(path).await?.len() as usize;
+        self.upload_range(
+            url,
+            path,
+            0..file_len,
+            Some(offset),
+            DEFAULT_CHUNK_SIZE,
+            on_progress,
+        )
+        .await
+    }
+
+    /// Upload the `range` of the file to the specified upload URL, continuing at `offset`
+    /// within the range or at the offset the server reports if it is not known.
+    async fn upload_range<F>(
+        &self,
+        url: &str,
+        path: &Path,
+        range: Range<usize>,
+        offset: Option<usize>,
+        chunk_size: usize,
+        on_progress: F,
+    ) -> Result<(), TusError>
+    where
+        F: Fn(UploadProgress),
+    {
+        let start = range.start;
+        let len = range.len();
+        let (mut progress, mut expires_at) = match offset {
+            // A wrong offset is corrected like any other offset mismatch
+            Some(offset) => (offset, None),
+            None => {
+                let info = self.with_retries(|| self.get_info(url)).await?;
+                if let Some(total_size) = info.total_size
+                    && len != total_size
+                {
+                    return Err(TusError::UnequalSizeError);
+                }
+                (info.bytes_uploaded, info.expires_at)
             }
-        }
+        };
+        let mut file = File::open(path).await?;
 
-        let mut reader = BufReader::new(file);
-        let mut buffer = vec![0; chunk_size];
-        let mut progress = info.bytes_uploaded;
+        let mut chunk_sizer = ChunkSizer::new(chunk_size, self.min_chunk_size, self.max_chunk_size);
+        let mut retries = 0;
+        let mut checksum_retries = 0;
 
-        reader.seek(SeekFrom::Start(progress as u64))?;
+        let report = |bytes_uploaded, expires_at| {
+            on_progress(UploadProgress {
+                bytes_uploaded,
+                total_size: len,
+                expires_at,
+            })
+        };
+        report(progress, expires_at);
 
-        loop {
-            let bytes_read = reader.read(&mut buffer)?;
-            if bytes_read == 0 {
-                return Err(TusError::FileReadError);
+        while progress < len {
+            let chunk_size = chunk_sizer.size().min(len - progress);
+            let chunk = read_chunk(&mut file, start + progress, chunk_size).await?;
+            let chunk_len = chunk.len();
+            let started = Instant::now();
+
+            match self.patch_chunk(url, progress, chunk).await {
+                Ok(info) => {
+                    chunk_sizer.record_success(chunk_len, started.elapsed());
+                    progress = info.bytes_uploaded;
+                    // The server may extend the expiration with every chunk
+                    expires_at = info.expires_at.or(expires_at);
+                    retries = 0;
+                    checksum_retries = 0;
+                    report(progress, expires_at);
+                }
+                Err(TusError::ChecksumMismatch) => {
+                    checksum_retries += 1;
+                    if checksum_retries > MAX_CHECKSUM_RETRIES {
+                        return Err(TusError::ChecksumMismatch);
+                    }
+                    log::warn!("Checksum mismatch at offset {progress}, sending chunk again");
+                }
+                Err(e) if e.is_transient() || matches!(e, TusError::WrongUploadOffsetError) => {
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return Err(e);
+                    }
+                    if e.is_transient() {
+                        chunk_sizer.record_failure();
+                        let delay = retry_delay(retries);
+                        log::warn!(
+                            "Upload failed at offset {progress}, retrying in {delay:?}: {e}"
+                        );
+                        tokio::time::sleep(delay).await;
+                    }
+                    // The server may have stored part of the chunk, continue from its offset
+                    match self.get_info(url).await {
+                        Ok(info) => {
+                            progress = info.bytes_uploaded;
+                            expires_at = info.expires_at.or(expires_at);
+                        }
+                        Err(e) if e.is_transient() => {}
+                        Err(e) => return Err(e),
+                    }
+                    report(progress, expires_at);
+                }
+                Err(e) => return Err(e),
             }
+        }
 
-            let req = self
-                .create_request(reqwest::Method::PATCH, url)
-                .header(CONTENT_TYPE, "application/offset+octet-stream")
-                .header(UPLOAD_OFFSET, progress.to_string())
-                .body(buffer[..bytes_read].to_vec());
+        Ok(())
+    }
 
-            let response = req.send().await?;
+    /// Create a partial upload of `len` bytes, which is later concatenated
+    /// with other partial uploads using the concatenation extension.
+    pub async fn create_partial(&self, url: &str, len: usize) -> Result<CreatedUpload, TusError> {
+        if !self.supports(TusExtension::Concatenation) {
+            return Err(TusError::UnsupportedExtension(TusExtension::Concatenation));
+        }
+        let req = self
+            .create_request(reqwest::Method::POST, url)
+            .header(UPLOAD_LENGTH, len.to_string())
+            .header(UPLOAD_CONCAT, "partial");
+        self.send_create(req).await
+    }
 
-            if response.status().as_u16() == 409 {
-                return Err(TusError::WrongUploadOffsetError);
-            }
+    /// Upload the rest of a partial upload that holds `len` bytes of the file starting at `start`.
+    /// `on_progress` is called after every chunk with the progress within the part.
+    pub async fn upload_partial<F>(
+        &self,
+        url: &str,
+        path: &Path,
+        start: usize,
+        len: usize,
+        on_progress: F,
+    ) -> Result<(), TusError>
+    where
+        F: Fn(UploadProgress),
+    {
+        self.upload_range(
+            url,
+            path,
+            start..start + len,
+            None,
+            DEFAULT_CHUNK_SIZE,
+            on_progress,
+        )
+        .await
+    }
 
-            if response.status().as_u16() == 404 {
-                return Err(TusError::NotFoundError);
-            }
+    /// Concatenate complete partial uploads, given by their `Location`, into a final upload
+    /// with the specified metadata.
+    pub async fn concatenate(
+        &self,
+        url: &str,
+        partial_locations: &[&str],
+        metadata: HashMap<String, String>,
+    ) -> Result<CreatedUpload, TusError> {
+        if !self.supports(TusExtension::Concatenation) {
+            return Err(TusError::UnsupportedExtension(TusExtension::Concatenation));
+        }
+        let mut req = self.create_request(reqwest::Method::POST, url).header(
+            UPLOAD_CONCAT,
+            format!("final;{}", partial_locations.join(" ")),
+        );
+        if !metadata.is_empty() {
+            req = req.header(UPLOAD_METADATA.to_owned(), encode_metadata(&metadata));
+        }
+        self.send_create(req).await
+    }
 
-            if response.status().as_u16() != 204 {
-                return Err(TusError::UnexpectedStatusCode(response.status().as_u16()));
-            }
+    /// Resolve a possibly relative `Location` header against the URL of the request.
+    pub fn resolve_location(&self, url: &str, location: &str) -> String {
+        match reqwest::Url::parse(url).and_then(|url| url.join(location)) {
+            Ok(resolved) => resolved.to_string(),
+            Err(_) => location.to_owned(),
+        }
+    }
+
+    /// Send a single chunk starting at `offset` and return the new offset reported by the server.
+    async fn patch_chunk(
+        &self,
+        url: &str,
+        offset: usize,
+        chunk: Bytes,
+    ) -> Result<UploadInfo, TusError> {
+        let mut req = self
+            .create_request(reqwest::Method::PATCH, url)
+            .timeout(chunk_timeout(chunk.len()))
+            .header(CONTENT_TYPE, "application/offset+octet-stream")
+            .header(UPLOAD_OFFSET, offset.to_string());
+
+        if let Some(algorithm) = self.checksum_algorithm {
+            req = req.header(
+                UPLOAD_CHECKSUM,
+                format!("{} {}", algorithm.name(), algorithm.checksum(&chunk)),
+            );
+        }
 
-            let upload_offset = match response.headers().get(UPLOAD_OFFSET) {
-                Some(offset) => offset,
-                None => return Err(TusError::MissingHeader(UPLOAD_OFFSET.to_owned())),
-            };
+        let response = req.body(chunk).send().await?;
 
-            progress = match upload_offset.to_str()?.parse() {
-                Ok(offset) => offset,
-                Err(e) => return Err(TusError::ParsingError(e)),
-            };
+        match response.status().as_u16() {
+            204 => {}
+            409 => return Err(TusError::WrongUploadOffsetError),
+            404 | 410 => return Err(TusError::NotFoundError),
+            460 => return Err(TusError::ChecksumMismatch),
+            status => return Err(TusError::UnexpectedStatusCode(status)),
+        }
+
+        let upload_offset = match response.headers().get(UPLOAD_OFFSET) {
+            Some(offset) => offset,
+            None => return Err(TusError::MissingHeader(UPLOAD_OFFSET.to_owned())),
+        };
+
+        let bytes_uploaded = match upload_offset.to_str()?.parse() {
+            Ok(offset) => offset,
+            Err(e) => return Err(TusError::ParsingError(e)),
+        };
+
+        Ok(UploadInfo {
+            bytes_uploaded,
+            total_size: None,
+            expires_at: parse_expires(response.headers()),
+        })
+    }
 
-            if progress >= file_len {
-                break;
+    /// Run `operation` again with exponential backoff as long as it fails with a transient error.
+    async fn with_retries<T, F, Fut>(&self, operation: F) -> Result<T, TusError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, TusError>>,
+    {
+        let mut retries = 0;
+        loop {
+            match operation().await {
+                Err(e) if e.is_transient() && retries < MAX_RETRIES => {
+                    retries += 1;
+                    let delay = retry_delay(retries);
+                    log::warn!("Request failed, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
             }
         }
+    }
 
-        Ok(())
+    /// Delete an upload on the server using the termination extension.
+    pub async fn terminate(&self, url: &str) -> Result<(), TusError> {
+        if !self.server_info.supports(TusExtension::Termination) {
+            return Err(TusError::UnsupportedExtension(TusExtension::Termination));
+        }
+
+        let response = self
+            .create_request(reqwest::Method::DELETE, url)
+            .send()
+            .await?;
+
+        match response.status().as_u16() {
+            204 => Ok(()),
+            404 | 410 => Err(TusError::NotFoundError),
+            status => Err(TusError::UnexpectedStatusCode(status)),
+        }
     }
 
     /// Create a file on the server including the specified metadata, receiving the upload URL of the file.
@@ -195,20 +552,50 @@ impl TusClient {
         url: &str,
         path: &Path,
         metadata: HashMap<String, String>,
-    ) -> Result<String, TusError> {
+    ) -> Result<CreatedUpload, TusError> {
+        let file_len = tokio::fs::metadata(path).await?.len() as usize;
+        self.check_max_upload_size(file_len)?;
+
         let mut req = self
             .create_request(reqwest::Method::POST, url)
-            .header(UPLOAD_LENGTH.to_owned(), path.metadata()?.len().to_string());
+            .header(UPLOAD_LENGTH.to_owned(), file_len.to_string());
 
         if !metadata.is_empty() {
-            let data = metadata
-                .iter()
-                .map(|(key, value)| format!("{} {}", key, base64::encode(value)))
-                .collect::<Vec<_>>()
-                .join(",");
-            req = req.header(UPLOAD_METADATA.to_owned(), data);
+            req = req.header(UPLOAD_METADATA.to_owned(), encode_metadata(&metadata));
+        }
+
+        // Send the first chunk right away, small files are then uploaded with a single request.
+        if self.supports(TusExtension::CreationWithUpload) && file_len > 0 {
+            let chunk_size =
+                ChunkSizer::new(DEFAULT_CHUNK_SIZE, self.min_chunk_size, self.max_chunk_size)
+                    .size();
+            let mut file = File::open(path).await?;
+            let chunk = read_chunk(&mut file, 0, chunk_size.min(file_len)).await?;
+            req = req
+                .timeout(chunk_timeout(chunk.len()))
+                .header(CONTENT_TYPE, "application/offset+octet-stream");
+            if let Some(algorithm) = self.checksum_algorithm {
+                req = req.header(
+                    UPLOAD_CHECKSUM,
+                    format!("{} {}", algorithm.name(), algorithm.checksum(&chunk)),
+                );
+            }
+            req = req.body(chunk);
+        }
+
+        self.send_create(req).await
+    }
+
+    /// Refuse files larger than the server accepts before creating an upload.
+    pub fn check_max_upload_size(&self, file_len: usize) -> Result<(), TusError> {
+        match self.server_info.max_upload_size {
+            Some(max_upload_size) if file_len > max_upload_size => Err(TusError::FileTooLarge),
+            _ => Ok(()),
         }
+    }
 
+    /// Send a creation request and return the created upload.
+    async fn send_create(&self, req: reqwest::RequestBuilder) -> Result<CreatedUpload, TusError> {
         let response = req.send().await?;
 
         if response.status().as_u16() == 413 {
@@ -225,8 +612,103 @@ impl TusClient {
             return Err(TusError::MissingHeader(LOCATION.to_owned()));
         }
 
-        Ok(location.unwrap().to_str()?.to_owned())
+        // Only present if data was sent with the creation request
+        let bytes_uploaded = match response.headers().get(UPLOAD_OFFSET) {
+            Some(offset) => offset.to_str()?.parse()?,
+            None => 0,
+        };
+
+        Ok(CreatedUpload {
+            location: location.unwrap().to_str()?.to_owned(),
+            bytes_uploaded,
+            expires_at: parse_expires(response.headers()),
+        })
+    }
+}
+
+/// Adapts the chunk size to the throughput measured while uploading.
+struct ChunkSizer {
+    size: usize,
+    min: usize,
+    max: usize,
+}
+
+impl ChunkSizer {
+    fn new(initial: usize, min: usize, max: usize) -> Self {
+        ChunkSizer {
+            size: initial.clamp(min, max),
+            min,
+            max,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Aim for chunks that take `TARGET_CHUNK_DURATION` at the measured throughput,
+    /// growing at most by a factor of two per chunk.
+    fn record_success(&mut self, bytes: usize, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64().max(0.001);
+        let throughput = bytes as f64 / elapsed;
+        let target = (throughput * TARGET_CHUNK_DURATION.as_secs_f64()) as usize;
+        self.size = target
+            .min(self.size.saturating_mul(2))
+            .clamp(self.min, self.max);
     }
+
+    /// Halve the chunk size after a failed request.
+    fn record_failure(&mut self) {
+        self.size = (self.size / 2).clamp(self.min, self.max);
+    }
+}
+
+/// Split a file of `file_len` bytes into at most `parts` ranges of `(start, len)`
+/// for partial uploads. An empty file is a single empty part.
+pub fn partial_ranges(file_len: usize, parts: usize) -> Vec<(usize, usize)> {
+    if file_len == 0 {
+        return vec![(0, 0)];
+    }
+    let part_len = file_len.div_ceil(parts.clamp(1, file_len));
+    (0..file_len)
+        .step_by(part_len)
+        .map(|start| (start, part_len.min(file_len - start)))
+        .collect()
+}
+
+/// Read up to `len` bytes at `offset` into a buffer that can be handed
+/// to a request body without copying.
+async fn read_chunk(file: &mut File, offset: usize, len: usize) -> Result<Bytes, TusError> {
+    file.seek(SeekFrom::Start(offset as u64)).await?;
+    let mut buffer = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut buffer).await?;
+    if buffer.is_empty() {
+        return Err(TusError::FileReadError);
+    }
+    Ok(Bytes::from(buffer))
+}
+
+/// Encode metadata for the `Upload-Metadata` header.
+fn encode_metadata(metadata: &HashMap<String, String>) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| format!("{} {}", key, base64::encode(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parse the `Upload-Expires` header, which uses the RFC 7231 datetime format.
+fn parse_expires(headers: &reqwest::header::HeaderMap) -> Option<DateTime<FixedOffset>> {
+    let expires = headers.get(UPLOAD_EXPIRES)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(expires).ok()
+}
+
+/// The earlier of two expiration dates, `None` if neither upload expires.
+pub fn earliest_expiry(
+    a: Option<DateTime<FixedOffset>>,
+    b: Option<DateTime<FixedOffset>>,
+) -> Option<DateTime<FixedOffset>> {
+    a.into_iter().chain(b).min()
 }
 
 /// Describes a file on the server.
@@ -236,6 +718,102 @@ pub struct UploadInfo {
     pub bytes_uploaded: usize,
     /// The total size of the file.
     pub total_size: Option<usize>,
+    /// When the server will remove the unfinished upload, if it expires at all.
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+
+/// Describes a file created on the server.
+#[derive(Debug)]
+pub struct CreatedUpload {
+    /// The upload URL as given by the server, which may be relative.
+    pub location: String,
+    /// How many bytes were sent with the creation request.
+    pub bytes_uploaded: usize,
+    /// When the server will remove the unfinished upload, if it expires at all.
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+
+/// Reported to the progress callback while uploading.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadProgress {
+    /// How many bytes the server confirmed.
+    pub bytes_uploaded: usize,
+    /// The total size of the file.
+    pub total_size: usize,
+    /// When the server will remove the unfinished upload, if it expires at all.
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+
+/// Describes the capabilities of the server.
+#[derive(Debug, Default)]
+pub struct ServerInfo {
+    /// The protocol versions supported by the server.
+    pub supported_versions: Vec<String>,
+    /// The extensions supported by the server.
+    pub extensions: Vec<TusExtension>,
+    /// The maximum allowed size of an upload in bytes.
+    pub max_upload_size: Option<usize>,
+    /// The checksum algorithms supported by the server.
+    pub supported_checksum_algorithms: Vec<ChecksumAlgorithm>,
+}
+
+impl ServerInfo {
+    /// Parse the headers of the response to an `OPTIONS` request.
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Result<ServerInfo, TusError> {
+        let header_list = |name: &str| -> Result<Vec<String>, TusError> {
+            match headers.get(name) {
+                Some(value) => Ok(value
+                    .to_str()?
+                    .split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect()),
+                None => Ok(vec![]),
+            }
+        };
+
+        let max_upload_size = match headers.get(TUS_MAX_SIZE) {
+            Some(max_size) => Some(max_size.to_str()?.parse::<usize>()?),
+            None => None,
+        };
+
+        Ok(ServerInfo {
+            supported_versions: header_list(TUS_VERSION)?,
+            extensions: header_list(TUS_EXTENSION)?
+                .iter()
+                .filter_map(|ext| ext.parse().ok())
+                .collect(),
+            max_upload_size,
+            supported_checksum_algorithms: header_list(TUS_CHECKSUM_ALGORITHM)?
+                .iter()
+                .filter_map(|alg| alg.parse().ok())
+                .collect(),
+        })
+    }
+
+    /// Whether the server speaks the protocol version of this client.
+    pub fn supports_client_version(&self) -> bool {
+        self.supported_versions.is_empty()
+            || self
+                .supported_versions
+                .iter()
+                .any(|v| v == PROTOCOL_VERSION)
+    }
+
+    /// Whether the server supports the given extension.
+    pub fn supports(&self, extension: TusExtension) -> bool {
+        self.extensions.contains(&extension)
+    }
+
+    /// The preferred checksum algorithm, if the server supports the checksum extension.
+    pub fn checksum_algorithm(&self) -> Option<ChecksumAlgorithm> {
+        if !self.supports(TusExtension::Checksum) {
+            return None;
+        }
+        ChecksumAlgorithm::PREFERENCE
+            .into_iter()
+            .find(|alg| self.supported_checksum_algorithms.contains(alg))
+    }
 }
 
 /// Enumerates the extensions to the tus protocol.
@@ -243,6 +821,8 @@ pub struct UploadInfo {
 pub enum TusExtension {
     /// The server supports creating files.
     Creation,
+    /// The server accepts data with the request creating a file.
+    CreationWithUpload,
     //// The server supports setting expiration time on files and uploads.
     Expiration,
     /// The server supports verifying checksums of uploaded chunks.
@@ -259,6 +839,7 @@ impl FromStr for TusExtension {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.trim().to_lowercase().as_str() {
             "creation" => Ok(TusExtension::Creation),
+            "creation-with-upload" => Ok(TusExtension::CreationWithUpload),
             "expiration" => Ok(TusExtension::Expiration),
             "checksum" => Ok(TusExtension::Checksum),
             "termination" => Ok(TusExtension::Termination),
@@ -267,3 +848,235 @@ impl FromStr for TusExtension {
         }
     }
 }
+
+/// Enumerates the checksum algorithms for the checksum extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Sha1,
+    Sha256,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    /// Algorithms in order of preference when the server supports several.
+    pub const PREFERENCE: [ChecksumAlgorithm; 3] = [
+        ChecksumAlgorithm::Sha256,
+        ChecksumAlgorithm::Sha1,
+        ChecksumAlgorithm::Md5,
+    ];
+
+    /// The name of the algorithm as used in the `Upload-Checksum` header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Md5 => "md5",
+        }
+    }
+
+    /// The base64 encoded checksum of `data`.
+    pub fn checksum(&self, data: &[u8]) -> String {
+        match self {
+            ChecksumAlgorithm::Sha1 => base64::encode(Sha1::digest(data)),
+            ChecksumAlgorithm::Sha256 => base64::encode(Sha256::digest(data)),
+            ChecksumAlgorithm::Md5 => base64::encode(Md5::digest(data)),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "sha1" => Ok(ChecksumAlgorithm::Sha1),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn server_info_from_options_headers() {
+        let server_info = ServerInfo::from_headers(&headers(&[
+            (TUS_VERSION, "1.0.0,0.2.2"),
+            (TUS_EXTENSION, "creation, expiration,checksum,unknown,"),
+            (TUS_MAX_SIZE, "1073741824"),
+            (TUS_CHECKSUM_ALGORITHM, "md5,sha1,crc32"),
+        ]))
+        .unwrap();
+        assert_eq!(server_info.supported_versions, vec!["1.0.0", "0.2.2"]);
+        assert_eq!(
+            server_info.extensions,
+            vec![
+                TusExtension::Creation,
+                TusExtension::Expiration,
+                TusExtension::Checksum
+            ]
+        );
+        assert_eq!(server_info.max_upload_size, Some(1073741824));
+        assert!(server_info.supports_client_version());
+        assert!(!server_info.supports(TusExtension::Termination));
+        // sha1 is preferred over md5, crc32 is not supported by the client
+        assert_eq!(
+            server_info.checksum_algorithm(),
+            Some(ChecksumAlgorithm::Sha1)
+        );
+    }
+
+    #[test]
+    fn server_info_without_headers() {
+        let server_info = ServerInfo::from_headers(&HeaderMap::new()).unwrap();
+        assert!(server_info.supports_client_version());
+        assert!(server_info.extensions.is_empty());
+        assert_eq!(server_info.max_upload_size, None);
+        assert_eq!(server_info.checksum_algorithm(), None);
+    }
+
+    #[test]
+    fn server_info_rejects_other_versions_and_invalid_sizes() {
+        let server_info = ServerInfo::from_headers(&headers(&[(TUS_VERSION, "0.2.2")])).unwrap();
+        assert!(!server_info.supports_client_version());
+        // Algorithms are only used with the checksum extension
+        let server_info =
+            ServerInfo::from_headers(&headers(&[(TUS_CHECKSUM_ALGORITHM, "sha256")])).unwrap();
+        assert_eq!(server_info.checksum_algorithm(), None);
+        assert!(ServerInfo::from_headers(&headers(&[(TUS_MAX_SIZE, "lots")])).is_err());
+    }
+
+    const KIB: usize = 1024;
+
+    #[test]
+    fn chunk_size_starts_within_limits() {
+        assert_eq!(
+            ChunkSizer::new(4 * KIB, 16 * KIB, 64 * KIB).size(),
+            16 * KIB
+        );
+        assert_eq!(
+            ChunkSizer::new(128 * KIB, 16 * KIB, 64 * KIB).size(),
+            64 * KIB
+        );
+        assert_eq!(
+            ChunkSizer::new(32 * KIB, 16 * KIB, 64 * KIB).size(),
+            32 * KIB
+        );
+    }
+
+    #[test]
+    fn chunk_size_grows_at_most_twofold() {
+        let mut sizer = ChunkSizer::new(16 * KIB, 16 * KIB, 1024 * KIB);
+        // Fast enough for far larger chunks
+        sizer.record_success(16 * KIB, Duration::from_millis(10));
+        assert_eq!(sizer.size(), 32 * KIB);
+        sizer.record_success(32 * KIB, Duration::from_millis(10));
+        assert_eq!(sizer.size(), 64 * KIB);
+    }
+
+    #[test]
+    fn chunk_size_follows_throughput() {
+        let mut sizer = ChunkSizer::new(64 * KIB, 16 * KIB, 1024 * KIB);
+        // 8 KiB/s fits 40 KiB into the target duration
+        sizer.record_success(64 * KIB, Duration::from_secs(8));
+        assert_eq!(sizer.size(), 40 * KIB);
+        // Slower than the minimum allows
+        sizer.record_success(KIB, Duration::from_secs(10));
+        assert_eq!(sizer.size(), 16 * KIB);
+    }
+
+    #[test]
+    fn chunk_size_stays_below_maximum() {
+        let mut sizer = ChunkSizer::new(48 * KIB, 16 * KIB, 64 * KIB);
+        sizer.record_success(48 * KIB, Duration::from_millis(10));
+        assert_eq!(sizer.size(), 64 * KIB);
+    }
+
+    #[test]
+    fn chunk_size_halves_on_failure() {
+        let mut sizer = ChunkSizer::new(64 * KIB, 16 * KIB, 64 * KIB);
+        sizer.record_failure();
+        assert_eq!(sizer.size(), 32 * KIB);
+        sizer.record_failure();
+        sizer.record_failure();
+        assert_eq!(sizer.size(), 16 * KIB);
+    }
+
+    #[test]
+    fn chunk_timeout_grows_with_chunk_size() {
+        assert_eq!(chunk_timeout(0), MIN_CHUNK_TIMEOUT);
+        assert_eq!(
+            chunk_timeout(4 * 1024 * KIB),
+            MIN_CHUNK_TIMEOUT + Duration::from_secs(256)
+        );
+    }
+
+    #[test]
+    fn parse_upload_expires() {
+        let expires_at = parse_expires(&headers(&[(
+            UPLOAD_EXPIRES,
+            "Wed, 25 Jun 2025 16:00:00 GMT",
+        )]));
+        assert_eq!(
+            expires_at,
+            DateTime::parse_from_rfc3339("2025-06-25T16:00:00+00:00").ok()
+        );
+        assert_eq!(
+            parse_expires(&headers(&[(UPLOAD_EXPIRES, "tomorrow")])),
+            None
+        );
+        assert_eq!(parse_expires(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn earliest_expiry_of_uploads() {
+        let earlier = DateTime::parse_from_rfc3339("2025-06-25T16:00:00+00:00").ok();
+        let later = DateTime::parse_from_rfc3339("2025-06-25T18:00:00+01:00").ok();
+        assert_eq!(earliest_expiry(later, earlier), earlier);
+        assert_eq!(earliest_expiry(None, later), later);
+        assert_eq!(earliest_expiry(earlier, None), earlier);
+        assert_eq!(earliest_expiry(None, None), None);
+    }
+
+    #[test]
+    fn resolve_relative_and_absolute_locations() {
+        let client = TusClient::new(reqwest::Client::new());
+        let endpoint = "https://fragdenstaat.de/api/v1/upload/";
+        assert_eq!(
+            client.resolve_location(endpoint, "/api/v1/upload/abc/"),
+            "https://fragdenstaat.de/api/v1/upload/abc/"
+        );
+        assert_eq!(
+            client.resolve_location(endpoint, "https://uploads.example.org/files/abc"),
+            "https://uploads.example.org/files/abc"
+        );
+    }
+
+    #[test]
+    fn partial_ranges_cover_file() {
+        assert_eq!(partial_ranges(10, 4), vec![(0, 3), (3, 3), (6, 3), (9, 1)]);
+        assert_eq!(partial_ranges(8, 4), vec![(0, 2), (2, 2), (4, 2), (6, 2)]);
+        assert_eq!(partial_ranges(8, 1), vec![(0, 8)]);
+    }
+
+    #[test]
+    fn partial_ranges_small_files() {
+        // The last part would start beyond the end of the file
+        assert_eq!(partial_ranges(5, 4), vec![(0, 2), (2, 2), (4, 1)]);
+        assert_eq!(partial_ranges(2, 4), vec![(0, 1), (1, 1)]);
+        assert_eq!(partial_ranges(1, 0), vec![(0, 1)]);
+        assert_eq!(partial_ranges(0, 4), vec![(0, 0)]);
+    }
+}
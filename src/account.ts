@@ -1,5 +1,6 @@
 import { onIonViewDidLeave, onIonViewWillEnter } from '@ionic/vue';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { onOpenUrl } from '@tauri-apps/plugin-deep-link';
 import router from './router.ts';
 
@@ -13,10 +14,18 @@ interface User {
 
 type MaybeUser = User | null
 
+// A Froide deployment the app can log in to
+export interface FroideInstance {
+    id: string;
+    name: string;
+    base_url: string;
+    client_id: string;
+    allowed_hosts: string[];
+}
+
 
 const DOMAIN = "fragdenstaat.de"
 const APP_ORIGIN = "https://app.fragdenstaat.de"
-const START_URL_ORIGIN = "https://fragdenstaat.de"
 const BASE_PATH = "/app/scanner/deep"
 export const LOGIN_PATH = "/login/"
 
@@ -27,12 +36,32 @@ class Account {
     #messages: string[] = [];
     #deepUrl: string | null = null;
     #mountedWithDeepUrl: boolean = false;
+    #instance: FroideInstance | null = null;
 
     constructor() {
         this.#loggedIn = false;
     }
 
+    async loadInstance(): Promise<FroideInstance | null> {
+        try {
+            this.#instance = await invoke<FroideInstance>('get_current_instance')
+        } catch (error) {
+            console.warn('Error getting instance!', error)
+        }
+        return this.#instance
+    }
+
+    get instance() {
+        return this.#instance;
+    }
+
+    // Whether the URL belongs to the website of the current instance
+    isInstanceUrl(url: URL): boolean {
+        return url.protocol === "https:" && !!this.#instance?.allowed_hosts.includes(url.hostname)
+    }
+
     async setupUser() {
+        await this.loadInstance()
         try {
             let response = await invoke<MaybeUser>('get_user')
             if (response) {
@@ -88,13 +117,17 @@ class Account {
 
     sanitizeStartUrl(startUrl: string): string | null {
         const url = new URL(startUrl)
-        if (url.origin === START_URL_ORIGIN) {
+        if (this.isInstanceUrl(url)) {
             return startUrl
         }
         return null
     }
 
-    async startLogin(startUrl: string | null = null): Promise<string | null> {
+    async startLogin(startUrl: string | null = null, instance: FroideInstance | null = null): Promise<string | null> {
+        if (instance !== null) {
+            // Start URLs are checked against the instance that is logged in to
+            this.#instance = instance
+        }
         if (startUrl !== null) {
             let tempUrl = new URL(startUrl)
             if (tempUrl.origin === APP_ORIGIN && tempUrl.pathname.startsWith(BASE_PATH)) {
@@ -111,7 +144,7 @@ class Account {
                     this.#deepUrl = startUrl
                     startUrl = null
                 }
-            } else if (tempUrl.origin === START_URL_ORIGIN) {
+            } else if (this.isInstanceUrl(tempUrl)) {
                 // If the URL is a deep link, set it as the deep URL
                 this.#deepUrl = startUrl
                 startUrl = null
@@ -131,7 +164,7 @@ class Account {
         }
         try {
             console.log("Starting OAuth with start_url:", startUrl)
-            let response = await invoke('start_oauth', { start_url: startUrl })
+            let response = await invoke('start_oauth', { start_url: startUrl, instance })
             console.log('OAuth completed!', response)
             if (response) {
                 await this.setupUser()
@@ -139,13 +172,14 @@ class Account {
             return null
         } catch (error) {
             console.error('Error!', error)
+            await this.loadInstance()
             return error!.toString()
         }
     }
 
-    async startLogout(): Promise<string | null> {
+    async startLogout(discardUploads: boolean = false): Promise<string | null> {
         try {
-            await invoke('logout')
+            await invoke('logout', { discard_uploads: discardUploads })
             this.logout()
             return null
         } catch (error) {
@@ -166,6 +200,12 @@ class Account {
         this.addMessage(`Sie sind jetzt ausgeloggt!`)
     }
 
+    expireSession() {
+        this.#loggedIn = false;
+        this.#user = null;
+        this.addMessage(`Ihre Sitzung ist abgelaufen, bitte loggen Sie sich erneut ein.`)
+    }
+
     addMessage(message: string) {
         this.#messages.push(message)
     }
@@ -179,6 +219,13 @@ class Account {
 
 export const account = new Account()
 
+listen('session-expired', () => {
+    if (account.isLoggedIn) {
+        account.expireSession()
+        router.push(LOGIN_PATH)
+    }
+})
+
 export const getDeepPath = (deepUrl: string) => {
     let url = new URL(deepUrl);
     let path = url.pathname.replace(BASE_PATH, "");
@@ -193,7 +240,7 @@ export const useLoggedOutDeepLinkNavigation = (startLoginFunc?: (url: string) =>
             if (urls !== null && urls.length > 0) {
                 const deepUrl = urls[0]
                 const url = new URL(deepUrl)
-                if (url.protocol !== "https:" || !url.origin.endsWith(DOMAIN)) {
+                if (url.protocol !== "https:" || !(url.origin.endsWith(DOMAIN) || account.isInstanceUrl(url))) {
                     console.warn('Invalid deep link URL:', url)
                     return
                 }
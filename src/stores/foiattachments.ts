@@ -1,6 +1,8 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { defineStore } from 'pinia';
 import { computed, ref } from 'vue';
+import type { CacheUpdate } from '../utils';
 
 
 type FoiAttachmentApi = {
@@ -45,7 +47,10 @@ export const useFoiAttachmentsStore = defineStore('foiattachments', () => {
     const attachments = ref<FoiAttachment[]>([])
     const attachmentMap = computed(() => new Map(attachments.value.map((att) => [att.id, att])))
 
+    let currentMessageId: FoiMessageId | null = null
+
     const getAttachments = async (foimessage_id: FoiMessageId): Promise<void> => {
+        currentMessageId = foimessage_id
         attachments.value = (await invoke<FoiAttachmentApi[]>('get_foiattachments', { foimessage_id })).map(att => makeFoiAttachment(att));
     };
 
@@ -66,8 +71,23 @@ export const useFoiAttachmentsStore = defineStore('foiattachments', () => {
 
 
     const clearAttachments = () => {
+        currentMessageId = null
         attachments.value = []
     }
 
+    listen<CacheUpdate<FoiAttachmentApi[]>>('foiattachment-list', (event) => {
+        if (event.payload.key === `attachments/${currentMessageId}`) {
+            attachments.value = event.payload.value.map(att => makeFoiAttachment(att))
+        }
+    });
+
+    listen<CacheUpdate<FoiAttachmentApi>>('foiattachment-updated', (event) => {
+        const attachment = makeFoiAttachment(event.payload.value)
+        const index = attachments.value.findIndex((att) => att.id === attachment.id)
+        if (index !== -1) {
+            attachments.value[index] = attachment
+        }
+    });
+
     return { attachments, attachmentMap, getAttachments, getAttachment, clearAttachments }
 })
\ No newline at end of file
@@ -1,9 +1,26 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { defineStore } from 'pinia';
 import { computed, ref } from 'vue';
-import { toLocaleDateString } from '../utils';
+import { type CacheUpdate, toLocaleDateString } from '../utils';
 
 
+export type MessageKind = "email" | "post" | "fax" | "upload" | "phone" | "visit" | "import" | "unknown";
+
+// Kinds that are logged in the app, email is handled on the website
+export const APP_MESSAGE_KINDS: MessageKind[] = ["post", "fax", "upload", "phone", "visit"];
+
+export const MESSAGE_KIND_LABELS: Record<MessageKind, string> = {
+    email: "E-Mail",
+    post: "Brief",
+    fax: "Fax",
+    upload: "Upload",
+    phone: "Telefonat",
+    visit: "Besuch / Akteneinsicht",
+    import: "Import",
+    unknown: "Unbekannt",
+};
+
 type FoiMessageApi = {
     id: number,
     resource_uri: String,
@@ -13,6 +30,11 @@ type FoiMessageApi = {
     is_draft: boolean,
     sender: string | null,
     subject: string,
+    kind: MessageKind,
+    sender_public_body: string | null,
+    recipient_public_body: string | null,
+    content: string,
+    registered_mail_date: string | null,
 };
 
 export type FoiMessage = FoiMessageApi & {
@@ -25,10 +47,20 @@ export type FoiMessage = FoiMessageApi & {
 export type CreateMessage = {
     request: String;
     timestamp: string;
-    kind: "post";
+    kind: MessageKind;
     is_response: boolean;
     sender_public_body: string | null;
     recipient_public_body: string | null;
+    subject?: string;
+    plaintext?: string;
+    registered_mail_date?: string;
+}
+
+export type UpdateDraftMessage = {
+    timestamp?: string;
+    sender_public_body?: string;
+    recipient_public_body?: string;
+    subject?: string;
 }
 
 const makeFoiMessage = (mes: FoiMessageApi): FoiMessage => {
@@ -48,8 +80,11 @@ export const useFoiMessagesStore = defineStore('foimessages', () => {
     const messages = ref<FoiMessage[]>([])
     const messageMap = computed(() => new Map(messages.value.map((message) => [message.id, message])))
 
+    let currentRequestId: FoiRequestId | null = null
+
     const getMessages = async (foirequestId: FoiRequestId): Promise<void> => {
-        messages.value = (await invoke<FoiMessageApi[]>('get_foimessages', { foirequest_id: foirequestId })).map(m => makeFoiMessage(m))
+        currentRequestId = foirequestId
+        messages.value = (await invoke<FoiMessageApi[]>('get_foimessages', { foirequest_id: foirequestId, kinds: APP_MESSAGE_KINDS })).map(m => makeFoiMessage(m))
     };
 
     const getMessage = async (messageId: number): Promise<FoiMessage> => {
@@ -72,9 +107,22 @@ export const useFoiMessagesStore = defineStore('foimessages', () => {
     }
 
     const clearMessages = () => {
+        currentRequestId = null
         messages.value = []
     }
 
+    listen<CacheUpdate<FoiMessageApi[]>>('foimessage-list', (event) => {
+        if (event.payload.key === `messages/${currentRequestId}`) {
+            messages.value = event.payload.value
+                .filter(m => APP_MESSAGE_KINDS.includes(m.kind))
+                .map(m => makeFoiMessage(m))
+        }
+    });
+
+    listen<CacheUpdate<FoiMessageApi>>('foimessage-updated', (event) => {
+        replaceMessage(makeFoiMessage(event.payload.value))
+    });
+
     const createMessage = async (message: CreateMessage): Promise<FoiMessage> => {
         try {
             const apiMessage = await invoke<FoiMessageApi>("create_foimessage", { message });
@@ -90,5 +138,29 @@ export const useFoiMessagesStore = defineStore('foimessages', () => {
         }
     }
 
-    return { messages, getMessage, getMessages, clearMessages, createMessage }
+    const replaceMessage = (message: FoiMessage) => {
+        const index = messages.value.findIndex((m) => m.id === message.id)
+        if (index !== -1) {
+            messages.value[index] = message
+        }
+    }
+
+    const updateMessage = async (messageId: number, update: UpdateDraftMessage): Promise<FoiMessage> => {
+        const message = makeFoiMessage(await invoke<FoiMessageApi>("update_foimessage", { foimessage_id: messageId, update }));
+        replaceMessage(message)
+        return message
+    }
+
+    const deleteMessage = async (messageId: number): Promise<void> => {
+        await invoke<boolean>("delete_foimessage", { foimessage_id: messageId });
+        messages.value = messages.value.filter((m) => m.id !== messageId)
+    }
+
+    const publishMessage = async (messageId: number): Promise<FoiMessage> => {
+        const message = makeFoiMessage(await invoke<FoiMessageApi>("publish_foimessage", { foimessage_id: messageId }));
+        replaceMessage(message)
+        return message
+    }
+
+    return { messages, getMessage, getMessages, clearMessages, createMessage, updateMessage, deleteMessage, publishMessage }
 })
\ No newline at end of file
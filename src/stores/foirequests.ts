@@ -2,14 +2,71 @@ import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import { defineStore } from 'pinia';
 import { computed, ref } from 'vue';
-import { toLocaleDateString } from '../utils';
+import { type CacheUpdate, toLocaleDateString } from '../utils';
+
+export type Jurisdiction = {
+    id: number;
+    name: string;
+    resource_uri: string;
+    slug: string;
+}
 
 export type PublicBody = {
     id: number;
     name: string;
     resource_uri: string;
+    slug: string;
+    other_names: string;
+    site_url: string;
+    jurisdiction: Jurisdiction | null;
+    number_of_requests: number;
+}
+
+export type PublicBodyFilter = {
+    query?: string;
+    jurisdiction?: number;
+}
+
+type PublicBodyPage = {
+    meta: { next: string | null, total_count: number };
+    objects: PublicBody[];
 }
 
+export const searchPublicBodies = async (filter: PublicBodyFilter, offset: number = 0): Promise<{ publicBodies: PublicBody[], totalCount: number, hasMore: boolean }> => {
+    const page = await invoke<PublicBodyPage>('search_publicbodies', { filter, offset })
+    return {
+        publicBodies: page.objects,
+        totalCount: page.meta.total_count,
+        hasMore: page.meta.next !== null,
+    }
+}
+
+
+export type FoiRequestStatus = "awaiting_user_confirmation" | "publicbody_needed" | "awaiting_publicbody_confirmation" | "awaiting_response" | "awaiting_classification" | "asleep" | "resolved" | "unknown";
+
+export type FoiRequestResolution = "" | "successful" | "partially_successful" | "not_held" | "refused" | "user_withdrew_costs" | "user_withdrew" | "unknown";
+
+export const STATUS_LABELS: Record<FoiRequestStatus, string> = {
+    awaiting_user_confirmation: "Wartet auf Bestätigung",
+    publicbody_needed: "Behörde fehlt",
+    awaiting_publicbody_confirmation: "Wartet auf Behörde",
+    awaiting_response: "Wartet auf Antwort",
+    awaiting_classification: "Antwort muss eingeordnet werden",
+    asleep: "Ruhend",
+    resolved: "Abgeschlossen",
+    unknown: "Unbekannt",
+};
+
+export const RESOLUTION_LABELS: Record<FoiRequestResolution, string> = {
+    "": "",
+    successful: "Erfolgreich",
+    partially_successful: "Teilweise erfolgreich",
+    not_held: "Information nicht vorhanden",
+    refused: "Abgelehnt",
+    user_withdrew_costs: "Wegen Kosten zurückgezogen",
+    user_withdrew: "Zurückgezogen",
+    unknown: "Unbekannt",
+};
 
 type FoiRequestApi = {
     id: number;
@@ -19,6 +76,44 @@ type FoiRequestApi = {
     created_at: string;
     last_message: string;
     public_body: PublicBody;
+    status: FoiRequestStatus;
+    resolution: FoiRequestResolution;
+    due_date: string | null;
+    resolved_on: string | null;
+    costs: number;
+    law: string | null;
+    jurisdiction: string | null;
+    public: boolean;
+    reference: string;
+};
+
+export type RequestOrdering = "newest" | "oldest" | "last_message" | "first_message";
+
+export type RequestFilter = {
+    query?: string;
+    status?: FoiRequestStatus;
+    public_body?: number;
+    jurisdiction?: number;
+    created_after?: string;
+    created_before?: string;
+    ordering?: RequestOrdering;
+};
+
+export type UpdateRequestStatus = {
+    status: "awaiting_response" | "resolved";
+    resolution: FoiRequestResolution;
+    costs?: number;
+};
+
+type RequestPage = {
+    meta: {
+        limit: number;
+        offset: number;
+        next: string | null;
+        previous: string | null;
+        total_count: number;
+    };
+    objects: FoiRequestApi[];
 };
 
 export type FoiRequest = FoiRequestApi & {
@@ -26,6 +121,8 @@ export type FoiRequest = FoiRequestApi & {
     created_at_label: string;
     last_message_date: Date;
     last_message_label: string;
+    status_label: string;
+    due_date_label: string | null;
     staffRequest: boolean;
 };
 
@@ -37,23 +134,43 @@ const makeFoiRequest = (req: FoiRequestApi, staffRequest: boolean = false): FoiR
         created_at_label: toLocaleDateString(new Date(req.created_at)),
         last_message_date: new Date(req.last_message),
         last_message_label: toLocaleDateString(new Date(req.last_message)),
+        status_label: req.resolution ? `${STATUS_LABELS[req.status]}: ${RESOLUTION_LABELS[req.resolution]}` : STATUS_LABELS[req.status],
+        due_date_label: req.due_date ? toLocaleDateString(new Date(req.due_date)) : null,
         staffRequest
     }
 }
 
 export const useFoiRequestsStore = defineStore('foirequests', () => {
 
-    const getRequests = async (): Promise<boolean> => {
-        return await invoke('get_foirequests');
+    // A full sync also picks up status changes and deleted requests
+    const getRequests = async (fullSync: boolean = false): Promise<boolean> => {
+        return await invoke('get_foirequests', { full_sync: fullSync });
     };
 
     const addFoirequest = (request: FoiRequestApi): FoiRequest => {
-        if (!requestMap.value.has(request.id)) {
-            let foirequest = makeFoiRequest(request, staffRequests.has(request.id))
+        let foirequest = makeFoiRequest(request, staffRequests.has(request.id))
+        let index = requests.value.findIndex((r) => r.id === request.id)
+        if (index === -1) {
             requests.value.push(foirequest);
-            return foirequest
+        } else {
+            // Replace cached version with fresh data
+            requests.value[index] = foirequest;
         }
-        return requestMap.value.get(request.id)!
+        return foirequest
+    }
+
+    const searchRequests = async (filter: RequestFilter, offset: number = 0): Promise<{ requests: FoiRequest[], totalCount: number, hasMore: boolean }> => {
+        const page = await invoke<RequestPage>('search_foirequests', { filter, offset })
+        return {
+            requests: page.objects.map((request) => makeFoiRequest(request, staffRequests.has(request.id))),
+            totalCount: page.meta.total_count,
+            hasMore: page.meta.next !== null,
+        }
+    }
+
+    const updateRequestStatus = async (requestId: number, update: UpdateRequestStatus): Promise<FoiRequest> => {
+        const request = await invoke<FoiRequestApi>('update_foirequest_status', { request_id: requestId, update })
+        return addFoirequest(request)
     }
 
     const staffRequests = new Set<number>()
@@ -83,5 +200,13 @@ export const useFoiRequestsStore = defineStore('foirequests', () => {
         });
     });
 
-    return { requests, getRequests, getRequest }
+    listen<CacheUpdate<FoiRequestApi>>('foirequest-updated', (event) => {
+        addFoirequest(event.payload.value)
+    });
+
+    listen<number[]>('foirequest-removed', (event) => {
+        requests.value = requests.value.filter((r) => !event.payload.includes(r.id))
+    });
+
+    return { requests, getRequests, getRequest, searchRequests, updateRequestStatus }
 })
\ No newline at end of file
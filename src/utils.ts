@@ -30,6 +30,12 @@ const localeOptions = {
     day: 'numeric' as const,
 };
 
+// Payload of events emitted when a cached object was revalidated
+export type CacheUpdate<T> = {
+    key: string;
+    value: T;
+};
+
 export function toLocaleDateString(date: Date): string {
     return date.toLocaleDateString('de-DE', localeOptions)
 }